@@ -0,0 +1,61 @@
+use std::{env, fs, path::PathBuf};
+
+// File in src/shaders without the extension, entry point and profile of every shader used by
+// spirv! in src/shaders.rs
+const SHADERS: &[(&str, &str, &str)] = &[
+    ("velocity_field", "vs_main", "vs_6_6"),
+    ("velocity_field", "ps_main", "ps_6_6"),
+    ("velocity_calculations", "cs_main", "cs_6_6"),
+    ("diagnostics", "cs_reduce_cells", "cs_6_6"),
+    ("diagnostics", "cs_reduce_partials", "cs_6_6"),
+    ("density_visualize", "vs_main", "vs_6_6"),
+    ("density_visualize", "ps_main", "ps_6_6"),
+    ("solver", "cs_advect", "cs_6_6"),
+    ("solver", "cs_divergence", "cs_6_6"),
+    ("solver", "cs_jacobi", "cs_6_6"),
+    ("solver", "cs_subtract_gradient", "cs_6_6"),
+];
+
+// Compiles the shaders to SPIR-V in OUT_DIR, where they are embedded from. Nothing is compiled with
+// the runtime-shaders feature, which compiles them at startup instead.
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/shaders");
+    if env::var_os("CARGO_FEATURE_RUNTIME_SHADERS").is_some() {
+        return;
+    }
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let dxc = hassle_rs::Dxc::new().expect("Failed to load the DXC shared library");
+    let compiler = dxc.create_compiler().unwrap();
+    let library = dxc.create_library().unwrap();
+
+    for (file, entry_point, profile) in SHADERS {
+        let path = format!("src/shaders/{}.hlsl", file);
+        let source = fs::read_to_string(&path).unwrap();
+        let blob = library.create_blob_with_encoding_from_str(&source).unwrap();
+
+        let result =
+            match compiler.compile(&blob, &path, entry_point, profile, &["-spirv"], None, &[]) {
+                Ok(result) => result,
+                Err(result) => {
+                    let error_blob = result
+                        .0
+                        .get_error_buffer()
+                        .map_err(hassle_rs::utils::HassleError::Win32Error)
+                        .unwrap();
+                    panic!(
+                        "Failed to compile {} {}:\n{}",
+                        path,
+                        entry_point,
+                        library.get_blob_as_string(&error_blob)
+                    );
+                }
+            };
+        fs::write(
+            out_dir.join(format!("{}.{}.spv", file, entry_point)),
+            result.get_result().unwrap().to_vec(),
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,20 @@
+use std::env;
+
+// The header is generated into OUT_DIR on every build so a binding that cbindgen can not express
+// fails the build. include/fluid_simulator.h is the checked-in copy shipped with releases, and is
+// only overwritten when FLUID_SIMULATOR_UPDATE_HEADER is set.
+fn main() {
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)).unwrap();
+    let bindings = cbindgen::generate_with_config(&crate_dir, config)
+        .expect("Unable to generate the C header");
+    bindings.write_to_file(format!("{}/fluid_simulator.h", out_dir));
+    if env::var_os("FLUID_SIMULATOR_UPDATE_HEADER").is_some() {
+        bindings.write_to_file(format!("{}/include/fluid_simulator.h", crate_dir));
+    }
+
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=FLUID_SIMULATOR_UPDATE_HEADER");
+}
@@ -0,0 +1,105 @@
+#ifndef FLUID_SIMULATOR_H
+#define FLUID_SIMULATOR_H
+
+/* Generated by cbindgen from src/lib.rs, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef enum FluidStatus {
+  FLUID_STATUS_OK = 0,
+  FLUID_STATUS_INVALID_ARGUMENT,
+  FLUID_STATUS_TOO_MANY_FORCE_FIELDS,
+  FLUID_STATUS_IO_ERROR,
+  FLUID_STATUS_INTERNAL_ERROR,
+} FluidStatus;
+
+typedef struct FluidSimulator FluidSimulator;
+
+typedef struct FluidParameters {
+  float time_step;
+  float forced_velocity[2];
+  float forced_density;
+  float velocity_dissipation;
+  float density_dissipation;
+  float gravity[2];
+} FluidParameters;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+const char *fluid_last_error(void);
+
+struct FluidSimulator *fluid_simulator_create(uint32_t grid_size_x,
+                                              uint32_t grid_size_y,
+                                              const char *adapter_name);
+
+struct FluidSimulator *fluid_simulator_create_from_scene(const char *path,
+                                                         const char *adapter_name);
+
+void fluid_simulator_destroy(struct FluidSimulator *simulator);
+
+enum FluidStatus fluid_simulator_grid_size(const struct FluidSimulator *simulator,
+                                           uint32_t *grid_size_x,
+                                           uint32_t *grid_size_y);
+
+uint64_t fluid_simulator_step_count(const struct FluidSimulator *simulator);
+
+float fluid_simulator_time(const struct FluidSimulator *simulator);
+
+enum FluidStatus fluid_simulator_get_parameters(const struct FluidSimulator *simulator,
+                                                struct FluidParameters *parameters);
+
+enum FluidStatus fluid_simulator_set_parameters(struct FluidSimulator *simulator,
+                                                const struct FluidParameters *parameters);
+
+enum FluidStatus fluid_simulator_add_radial_force(struct FluidSimulator *simulator,
+                                                  float center_x,
+                                                  float center_y,
+                                                  float radius,
+                                                  float strength);
+
+enum FluidStatus fluid_simulator_add_vortex_force(struct FluidSimulator *simulator,
+                                                  float center_x,
+                                                  float center_y,
+                                                  float radius,
+                                                  float strength);
+
+enum FluidStatus fluid_simulator_add_wind_force(struct FluidSimulator *simulator,
+                                                float min_x,
+                                                float min_y,
+                                                float max_x,
+                                                float max_y,
+                                                float force_x,
+                                                float force_y);
+
+enum FluidStatus fluid_simulator_clear_force_fields(struct FluidSimulator *simulator);
+
+enum FluidStatus fluid_simulator_step(struct FluidSimulator *simulator, uint32_t steps);
+
+enum FluidStatus fluid_simulator_reset(struct FluidSimulator *simulator);
+
+enum FluidStatus fluid_simulator_read_velocity(const struct FluidSimulator *simulator,
+                                               float *output,
+                                               size_t length);
+
+enum FluidStatus fluid_simulator_read_density(const struct FluidSimulator *simulator,
+                                              float *output,
+                                              size_t length);
+
+enum FluidStatus fluid_simulator_write_fields(struct FluidSimulator *simulator,
+                                              const float *velocity,
+                                              const float *density,
+                                              size_t cell_count);
+
+enum FluidStatus fluid_simulator_load_obstacle_mask(struct FluidSimulator *simulator,
+                                                    const char *path);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* FLUID_SIMULATOR_H */
@@ -0,0 +1,480 @@
+// C ABI for linking the simulator into native hosts. A simulator is created headless on its own
+// device, configured, stepped, read back and destroyed through an opaque pointer. Fields are row
+// major floats starting from the bottom left cell, with two floats per cell for velocity.
+//
+// Every pointer argument must be valid for the duration of the call, and a simulator must not be
+// used from two threads at the same time. Functions that fail return FLUID_STATUS_* codes or NULL,
+// and fluid_last_error describes the last failure on the calling thread. A panic inside the
+// simulator is caught and returned as FLUID_STATUS_INTERNAL_ERROR or NULL; the only call that is
+// safe on the simulator afterwards is fluid_simulator_destroy.
+#![allow(clippy::missing_safety_doc)]
+
+use std::{
+    any::Any,
+    cell::RefCell,
+    ffi::{CStr, CString},
+    os::raw::c_char,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    ptr,
+};
+
+use simulator::{
+    device, scene::Scene, FluidSimulator as Simulator, ForceField, MAX_FORCE_FIELDS, MAX_GRID_SIZE,
+};
+
+// Only used by the visualization pipelines, which the C API does not draw with
+const SURFACE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+pub struct FluidSimulator {
+    simulator: Simulator,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FluidStatus {
+    Ok = 0,
+    InvalidArgument,
+    TooManyForceFields,
+    IoError,
+    // A panic was caught, see fluid_last_error
+    InternalError,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FluidParameters {
+    pub time_step: f32,
+    pub forced_velocity: [f32; 2],
+    pub forced_density: f32,
+    pub velocity_dissipation: f32,
+    pub density_dissipation: f32,
+    pub gravity: [f32; 2],
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: impl ToString) {
+    let message = CString::new(message.to_string().replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|error| *error.borrow_mut() = Some(message));
+}
+
+fn fail(status: FluidStatus, message: impl ToString) -> FluidStatus {
+    set_last_error(message);
+    status
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+// Runs the body of an exported function. Unwinding into the C caller is undefined behaviour, so a
+// panic is caught, recorded as the last error and replaced by on_panic.
+fn guard<T>(on_panic: T, body: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|payload| {
+        set_last_error(format!("internal error: {}", panic_message(&*payload)));
+        on_panic
+    })
+}
+
+// NULL strings are None, invalid UTF-8 is an error
+unsafe fn optional_str<'a>(string: *const c_char) -> Result<Option<&'a str>, String> {
+    if string.is_null() {
+        return Ok(None);
+    }
+    CStr::from_ptr(string)
+        .to_str()
+        .map(Some)
+        .map_err(|_| "string is not valid UTF-8".to_string())
+}
+
+unsafe fn create(
+    adapter_name: *const c_char,
+    build: impl FnOnce(
+        std::sync::Arc<wgpu::Device>,
+        std::sync::Arc<wgpu::Queue>,
+    ) -> Result<Simulator, String>,
+) -> *mut FluidSimulator {
+    guard(ptr::null_mut(), || {
+        let result = optional_str(adapter_name).and_then(|adapter_name| {
+            let (device, queue, _info) =
+                device::create_headless_device(wgpu::Backends::PRIMARY, adapter_name)
+                    .map_err(|error| error.to_string())?;
+            build(device, queue)
+        });
+        match result {
+            Ok(simulator) => Box::into_raw(Box::new(FluidSimulator { simulator })),
+            Err(message) => {
+                set_last_error(message);
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+// Message of the last failure on this thread, or NULL. Valid until the next failing call.
+#[no_mangle]
+pub extern "C" fn fluid_last_error() -> *const c_char {
+    LAST_ERROR.with(|error| {
+        error
+            .borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+// Creates a simulator with empty fields. adapter_name picks the first GPU whose name contains it
+// and can be NULL. Returns NULL on failure.
+#[no_mangle]
+pub unsafe extern "C" fn fluid_simulator_create(
+    grid_size_x: u32,
+    grid_size_y: u32,
+    adapter_name: *const c_char,
+) -> *mut FluidSimulator {
+    let valid_size = 1..=MAX_GRID_SIZE;
+    if !valid_size.contains(&(grid_size_x as usize))
+        || !valid_size.contains(&(grid_size_y as usize))
+    {
+        set_last_error(format!(
+            "grid size must be between 1 and {} in each dimension",
+            MAX_GRID_SIZE
+        ));
+        return ptr::null_mut();
+    }
+    create(adapter_name, |device, queue| {
+        Ok(Simulator::with_device(
+            device,
+            queue,
+            SURFACE_FORMAT,
+            grid_size_x as usize,
+            grid_size_y as usize,
+        ))
+    })
+}
+
+// Creates a simulator from a RON or TOML scene file. Returns NULL on failure.
+#[no_mangle]
+pub unsafe extern "C" fn fluid_simulator_create_from_scene(
+    path: *const c_char,
+    adapter_name: *const c_char,
+) -> *mut FluidSimulator {
+    let path = match optional_str(path) {
+        Ok(Some(path)) => Path::new(path),
+        Ok(None) => {
+            set_last_error("scene path is NULL");
+            return ptr::null_mut();
+        }
+        Err(message) => {
+            set_last_error(message);
+            return ptr::null_mut();
+        }
+    };
+    create(adapter_name, |device, queue| {
+        let error = |error: std::io::Error| format!("{}: {}", path.display(), error);
+        let scene = Scene::load(path).map_err(error)?;
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        scene
+            .build(&device, &queue, SURFACE_FORMAT, directory)
+            .map_err(error)
+    })
+}
+
+// Accepts NULL
+#[no_mangle]
+pub unsafe extern "C" fn fluid_simulator_destroy(simulator: *mut FluidSimulator) {
+    if !simulator.is_null() {
+        guard((), || drop(Box::from_raw(simulator)));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn fluid_simulator_grid_size(
+    simulator: *const FluidSimulator,
+    grid_size_x: *mut u32,
+    grid_size_y: *mut u32,
+) -> FluidStatus {
+    guard(FluidStatus::InternalError, || {
+        let (x, y) = (*simulator).simulator.grid_size();
+        *grid_size_x = x as u32;
+        *grid_size_y = y as u32;
+        FluidStatus::Ok
+    })
+}
+
+// 0 if a panic was caught
+#[no_mangle]
+pub unsafe extern "C" fn fluid_simulator_step_count(simulator: *const FluidSimulator) -> u64 {
+    guard(0, || (*simulator).simulator.step_count())
+}
+
+// 0 if a panic was caught
+#[no_mangle]
+pub unsafe extern "C" fn fluid_simulator_time(simulator: *const FluidSimulator) -> f32 {
+    guard(0.0, || (*simulator).simulator.time())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn fluid_simulator_get_parameters(
+    simulator: *const FluidSimulator,
+    parameters: *mut FluidParameters,
+) -> FluidStatus {
+    guard(FluidStatus::InternalError, || {
+        let current = (*simulator).simulator.parameters();
+        *parameters = FluidParameters {
+            time_step: current.time_step,
+            forced_velocity: current.forced_velocity.into(),
+            forced_density: current.forced_density,
+            velocity_dissipation: current.velocity_dissipation,
+            density_dissipation: current.density_dissipation,
+            gravity: current.gravity.into(),
+        };
+        FluidStatus::Ok
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn fluid_simulator_set_parameters(
+    simulator: *mut FluidSimulator,
+    parameters: *const FluidParameters,
+) -> FluidStatus {
+    guard(FluidStatus::InternalError, || {
+        let parameters = &*parameters;
+        (*simulator)
+            .simulator
+            .set_parameters(&simulator::scene::Parameters {
+                time_step: parameters.time_step,
+                forced_velocity: parameters.forced_velocity.into(),
+                forced_density: parameters.forced_density,
+                velocity_dissipation: parameters.velocity_dissipation,
+                density_dissipation: parameters.density_dissipation,
+                gravity: parameters.gravity.into(),
+            });
+        FluidStatus::Ok
+    })
+}
+
+unsafe fn add_force_field(simulator: *mut FluidSimulator, field: ForceField) -> FluidStatus {
+    guard(FluidStatus::InternalError, || {
+        let force_fields = &mut (*simulator).simulator.force_fields;
+        if force_fields.len() == MAX_FORCE_FIELDS {
+            return fail(
+                FluidStatus::TooManyForceFields,
+                format!("at most {} force fields are supported", MAX_FORCE_FIELDS),
+            );
+        }
+        force_fields.push(field);
+        FluidStatus::Ok
+    })
+}
+
+// Force fields are the momentum emitters. Positions are in grid space, from (0, 0) to (1, 1).
+#[no_mangle]
+pub unsafe extern "C" fn fluid_simulator_add_radial_force(
+    simulator: *mut FluidSimulator,
+    center_x: f32,
+    center_y: f32,
+    radius: f32,
+    strength: f32,
+) -> FluidStatus {
+    add_force_field(
+        simulator,
+        ForceField::Radial {
+            center: [center_x, center_y].into(),
+            radius,
+            strength,
+        },
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn fluid_simulator_add_vortex_force(
+    simulator: *mut FluidSimulator,
+    center_x: f32,
+    center_y: f32,
+    radius: f32,
+    strength: f32,
+) -> FluidStatus {
+    add_force_field(
+        simulator,
+        ForceField::Vortex {
+            center: [center_x, center_y].into(),
+            radius,
+            strength,
+        },
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn fluid_simulator_add_wind_force(
+    simulator: *mut FluidSimulator,
+    min_x: f32,
+    min_y: f32,
+    max_x: f32,
+    max_y: f32,
+    force_x: f32,
+    force_y: f32,
+) -> FluidStatus {
+    add_force_field(
+        simulator,
+        ForceField::Wind {
+            min: [min_x, min_y].into(),
+            max: [max_x, max_y].into(),
+            force: [force_x, force_y].into(),
+        },
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn fluid_simulator_clear_force_fields(
+    simulator: *mut FluidSimulator,
+) -> FluidStatus {
+    guard(FluidStatus::InternalError, || {
+        (*simulator).simulator.force_fields.clear();
+        FluidStatus::Ok
+    })
+}
+
+// Runs the steps and submits them to the GPU without waiting for them to finish
+#[no_mangle]
+pub unsafe extern "C" fn fluid_simulator_step(
+    simulator: *mut FluidSimulator,
+    steps: u32,
+) -> FluidStatus {
+    guard(FluidStatus::InternalError, || {
+        for _ in 0..steps {
+            (*simulator).simulator.run_step();
+        }
+        FluidStatus::Ok
+    })
+}
+
+// Restores the initial fields and obstacles and sets the time back to zero
+#[no_mangle]
+pub unsafe extern "C" fn fluid_simulator_reset(simulator: *mut FluidSimulator) -> FluidStatus {
+    guard(FluidStatus::InternalError, || {
+        (*simulator).simulator.reset();
+        FluidStatus::Ok
+    })
+}
+
+unsafe fn copy_field(data: &[f32], output: *mut f32, length: usize) -> FluidStatus {
+    if output.is_null() || length != data.len() {
+        return fail(
+            FluidStatus::InvalidArgument,
+            format!("expected a buffer of {} floats", data.len()),
+        );
+    }
+    ptr::copy_nonoverlapping(data.as_ptr(), output, length);
+    FluidStatus::Ok
+}
+
+// Waits for the submitted steps and copies the velocity into output, which holds
+// 2 * grid_size_x * grid_size_y floats
+#[no_mangle]
+pub unsafe extern "C" fn fluid_simulator_read_velocity(
+    simulator: *const FluidSimulator,
+    output: *mut f32,
+    length: usize,
+) -> FluidStatus {
+    guard(FluidStatus::InternalError, || {
+        let velocity = (*simulator).simulator.read_velocity_field();
+        let data: Vec<f32> = velocity
+            .iter()
+            .flat_map(|velocity| [velocity.x, velocity.y])
+            .collect();
+        copy_field(&data, output, length)
+    })
+}
+
+// Waits for the submitted steps and copies the density into output, which holds
+// grid_size_x * grid_size_y floats
+#[no_mangle]
+pub unsafe extern "C" fn fluid_simulator_read_density(
+    simulator: *const FluidSimulator,
+    output: *mut f32,
+    length: usize,
+) -> FluidStatus {
+    guard(FluidStatus::InternalError, || {
+        let density = (*simulator).simulator.read_density_field();
+        copy_field(&density, output, length)
+    })
+}
+
+// Overwrites the current fields. cell_count must be grid_size_x * grid_size_y, with two velocity
+// floats per cell. The initial fields used by reset are kept.
+#[no_mangle]
+pub unsafe extern "C" fn fluid_simulator_write_fields(
+    simulator: *mut FluidSimulator,
+    velocity: *const f32,
+    density: *const f32,
+    cell_count: usize,
+) -> FluidStatus {
+    guard(FluidStatus::InternalError, || {
+        let simulator = &mut (*simulator).simulator;
+        let (grid_size_x, grid_size_y) = simulator.grid_size();
+        if velocity.is_null() || density.is_null() || cell_count != grid_size_x * grid_size_y {
+            return fail(
+                FluidStatus::InvalidArgument,
+                format!("expected fields of {} cells", grid_size_x * grid_size_y),
+            );
+        }
+
+        let velocity: Vec<_> = std::slice::from_raw_parts(velocity, 2 * cell_count)
+            .chunks_exact(2)
+            .map(|velocity| [velocity[0], velocity[1]].into())
+            .collect();
+        let density = std::slice::from_raw_parts(density, cell_count);
+        simulator.write_fields(&velocity, density);
+        FluidStatus::Ok
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn fluid_simulator_load_obstacle_mask(
+    simulator: *mut FluidSimulator,
+    path: *const c_char,
+) -> FluidStatus {
+    let path = match optional_str(path) {
+        Ok(Some(path)) => path,
+        Ok(None) => return fail(FluidStatus::InvalidArgument, "path is NULL"),
+        Err(message) => return fail(FluidStatus::InvalidArgument, message),
+    };
+    guard(FluidStatus::InternalError, || {
+        match (*simulator).simulator.load_obstacle_mask(path) {
+            Ok(()) => FluidStatus::Ok,
+            Err(error) => fail(FluidStatus::IoError, format!("{}: {}", path, error)),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guard_catches_panics() {
+        let status = guard(FluidStatus::InternalError, || -> FluidStatus {
+            panic!("step {} failed", 3)
+        });
+        assert_eq!(status, FluidStatus::InternalError);
+        let message = unsafe { CStr::from_ptr(fluid_last_error()) };
+        assert_eq!(message.to_str().unwrap(), "internal error: step 3 failed");
+
+        assert_eq!(guard(0, || 7), 7);
+    }
+
+    #[test]
+    fn rejects_invalid_grid_size() {
+        for (x, y) in [(0, 16), (16, MAX_GRID_SIZE as u32 + 1)] {
+            let simulator = unsafe { fluid_simulator_create(x, y, ptr::null()) };
+            assert!(simulator.is_null());
+            assert!(!fluid_last_error().is_null());
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+
+use fluid_simulator::{image_import::Channel, MAX_GRID_SIZE};
+
+#[derive(Parser, Debug)]
+#[clap(name = "Fluid Simulator", about = "Interactive GPU fluid simulator")]
+pub struct Options {
+    #[clap(
+        long,
+        help = "Scene to start from, RON or TOML. Its grid size is replaced by --grid-size when both are given."
+    )]
+    pub scene: Option<PathBuf>,
+
+    #[clap(long, value_parser = parse_grid_size, help = "Grid size as <X>x<Y>, e.g. 64x32")]
+    pub grid_size: Option<(usize, usize)>,
+
+    #[clap(long, value_parser = parse_size, default_value = "1920x1080", help = "Window size as <WIDTH>x<HEIGHT>, also the size of the frames when headless")]
+    pub window_size: (usize, usize),
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "mailbox",
+        help = "Present mode of the window surface"
+    )]
+    pub present_mode: PresentMode,
+
+    #[clap(
+        long,
+        value_enum,
+        help = "Graphics backend to use, rend3 picks one when not given"
+    )]
+    pub backend: Option<Backend>,
+
+    #[clap(
+        long,
+        help = "Name of the adapter to use, rend3 picks one when not given"
+    )]
+    pub adapter: Option<String>,
+
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Exits after running this many steps"
+    )]
+    pub steps: Option<u64>,
+
+    #[clap(
+        long,
+        requires = "steps",
+        help = "Runs without a window for --steps steps, writing the requested outputs, then exits"
+    )]
+    pub headless: bool,
+
+    #[clap(long, help = "Starts paused, for inspecting the initial conditions")]
+    pub paused: bool,
+
+    #[clap(long, help = "PNG to use as the initial density")]
+    pub dye: Option<PathBuf>,
+
+    #[clap(long, value_parser = parse_channel, default_value = "luminance", help = "Channel of the --dye image to use")]
+    pub dye_channel: Channel,
+
+    #[clap(
+        long,
+        help = "PNG to use as an obstacle mask, bright opaque pixels are solid"
+    )]
+    pub obstacle_mask: Option<PathBuf>,
+
+    #[clap(long, help = "Writes a .vti file every step into the directory")]
+    pub vtk_dir: Option<PathBuf>,
+
+    #[clap(
+        long,
+        help = "Writes .npz archives into the directory, every --numpy-interval steps"
+    )]
+    pub numpy_dir: Option<PathBuf>,
+
+    #[clap(
+        long,
+        default_value = "1",
+        help = "Steps between two exports to --numpy-dir"
+    )]
+    pub numpy_interval: u64,
+
+    #[clap(
+        long,
+        help = "Writes a PNG of the visualization every step into the directory, numbered from zero. Frames that are already there are not overwritten"
+    )]
+    pub frames_dir: Option<PathBuf>,
+
+    #[clap(long, help = "Snapshot file written on exit after --steps")]
+    pub snapshot: Option<PathBuf>,
+
+    #[clap(
+        long,
+        help = "CSV file with the total density, kinetic energy and maximum speed of every step, headless only"
+    )]
+    pub stats: Option<PathBuf>,
+
+    #[clap(
+        long,
+        requires = "headless",
+        conflicts_with = "frames_dir",
+        help = "Runs the simulation on the CPU without using a GPU, headless only. Frames can not be recorded."
+    )]
+    pub cpu: bool,
+
+    #[clap(
+        long,
+        requires = "headless",
+        conflicts_with = "cpu",
+        help = "Runs the CPU reference solver alongside the GPU and fails if the fields differ by more than --tolerance after --steps steps"
+    )]
+    pub compare_cpu: bool,
+
+    #[clap(
+        long,
+        default_value = "1e-4",
+        help = "Largest absolute difference per field component accepted by --compare-cpu"
+    )]
+    pub tolerance: f32,
+
+    #[clap(
+        long,
+        conflicts_with = "headless",
+        help = "Runs flows with known solutions at several grid sizes and time steps without a window, prints the error norms and observed orders of accuracy, then exits"
+    )]
+    pub verify: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PresentMode {
+    Immediate,
+    Mailbox,
+    Fifo,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    Vulkan,
+    Metal,
+    Dx12,
+    Dx11,
+    Gl,
+}
+
+impl From<PresentMode> for rend3::types::PresentMode {
+    fn from(mode: PresentMode) -> Self {
+        match mode {
+            PresentMode::Immediate => rend3::types::PresentMode::Immediate,
+            PresentMode::Mailbox => rend3::types::PresentMode::Mailbox,
+            PresentMode::Fifo => rend3::types::PresentMode::Fifo,
+        }
+    }
+}
+
+impl From<Backend> for wgpu::Backend {
+    fn from(backend: Backend) -> Self {
+        match backend {
+            Backend::Vulkan => wgpu::Backend::Vulkan,
+            Backend::Metal => wgpu::Backend::Metal,
+            Backend::Dx12 => wgpu::Backend::Dx12,
+            Backend::Dx11 => wgpu::Backend::Dx11,
+            Backend::Gl => wgpu::Backend::Gl,
+        }
+    }
+}
+
+fn parse_size(value: &str) -> Result<(usize, usize), String> {
+    let (x, y) = value
+        .split_once('x')
+        .ok_or_else(|| format!("expected <X>x<Y>, got {}", value))?;
+    let parse = |dimension: &str| match dimension.trim().parse() {
+        Ok(0) | Err(_) => Err(format!("invalid size {}", value)),
+        Ok(dimension) => Ok(dimension),
+    };
+    Ok((parse(x)?, parse(y)?))
+}
+
+// Same bounds as the grid sizes of scene files
+fn parse_grid_size(value: &str) -> Result<(usize, usize), String> {
+    let (x, y) = parse_size(value)?;
+    if x > MAX_GRID_SIZE || y > MAX_GRID_SIZE {
+        return Err(format!(
+            "grid size {} is larger than {}x{}",
+            value, MAX_GRID_SIZE, MAX_GRID_SIZE
+        ));
+    }
+    Ok((x, y))
+}
+
+fn parse_channel(value: &str) -> Result<Channel, String> {
+    Channel::from_name(value).ok_or_else(|| {
+        let names: Vec<_> = Channel::ALL.iter().map(|channel| channel.name()).collect();
+        format!("expected one of {}", names.join(", "))
+    })
+}
@@ -0,0 +1,323 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use glam::Vec2;
+
+use fluid_simulator::{
+    cpu_solver::{CpuSimulation, CpuSolver},
+    device, image_capture,
+    scene::Scene,
+    FluidSimulator,
+};
+
+use crate::{cli::Options, Exports};
+
+// Format the visualizations are rendered in for frame captures
+const FRAME_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+fn exit_with_error(message: String) -> ! {
+    println!("{}", message);
+    std::process::exit(1);
+}
+
+fn exit_with_write_error(path: &Path, error: io::Error) -> ! {
+    exit_with_error(format!("Failed to write {}: {}", path.display(), error))
+}
+
+pub(crate) fn create_device(options: &Options) -> (Arc<wgpu::Device>, Arc<wgpu::Queue>) {
+    let backends = options.backend.map_or(wgpu::Backends::PRIMARY, |backend| {
+        wgpu::Backend::from(backend).into()
+    });
+    let (device, queue, info) =
+        device::create_headless_device(backends, options.adapter.as_deref())
+            .unwrap_or_else(|error| exit_with_error(error.to_string()));
+    println!("Running headless on {} ({:?})", info.name, info.backend);
+    (device, queue)
+}
+
+// State the outputs are written from, on the GPU or on the CPU
+trait Simulation: Exports {
+    fn step(&mut self);
+    fn time(&self) -> f32;
+    fn fields(&self) -> (Vec<Vec2>, Vec<f32>);
+    fn save_snapshot(&self, path: &Path) -> io::Result<()>;
+}
+
+// Optionally runs the CPU solver alongside for --compare-cpu
+struct Gpu {
+    fluid_simulator: FluidSimulator,
+    cpu_solver: Option<CpuSolver>,
+}
+
+impl Exports for Gpu {
+    fn step_count(&self) -> u64 {
+        self.fluid_simulator.step_count()
+    }
+
+    fn export_vtk(&self, path: &Path) -> io::Result<()> {
+        self.fluid_simulator.export_vtk(path)
+    }
+
+    fn export_npy(&self, velocity_path: &Path, density_path: &Path) -> io::Result<()> {
+        self.fluid_simulator.export_npy(velocity_path, density_path)
+    }
+
+    fn export_npz(&self, path: &Path) -> io::Result<()> {
+        self.fluid_simulator.export_npz(path)
+    }
+}
+
+impl Simulation for Gpu {
+    fn step(&mut self) {
+        self.fluid_simulator.run_step();
+        // Uses the obstacles where run_step moved them
+        if let Some(cpu_solver) = &mut self.cpu_solver {
+            cpu_solver.step(&self.fluid_simulator);
+        }
+    }
+
+    fn time(&self) -> f32 {
+        self.fluid_simulator.time()
+    }
+
+    fn fields(&self) -> (Vec<Vec2>, Vec<f32>) {
+        (
+            self.fluid_simulator.read_velocity_field(),
+            self.fluid_simulator.read_density_field(),
+        )
+    }
+
+    fn save_snapshot(&self, path: &Path) -> io::Result<()> {
+        self.fluid_simulator.save_snapshot(path)
+    }
+}
+
+impl Simulation for CpuSimulation {
+    fn step(&mut self) {
+        self.step();
+    }
+
+    fn time(&self) -> f32 {
+        self.time()
+    }
+
+    fn fields(&self) -> (Vec<Vec2>, Vec<f32>) {
+        (self.solver.velocity.clone(), self.solver.density.clone())
+    }
+
+    fn save_snapshot(&self, path: &Path) -> io::Result<()> {
+        self.save_snapshot(path)
+    }
+}
+
+// Writes the total density, kinetic energy and maximum speed of the current state as a CSV row
+fn write_statistics(
+    writer: &mut impl Write,
+    simulation: &impl Simulation,
+    grid_size: (usize, usize),
+) -> io::Result<()> {
+    let cell_area = 1.0 / (grid_size.0 * grid_size.1) as f32;
+    let (velocity, density) = simulation.fields();
+
+    let total_density = density.iter().sum::<f32>() * cell_area;
+    let kinetic_energy = velocity
+        .iter()
+        .map(|velocity| 0.5 * velocity.length_squared())
+        .sum::<f32>()
+        * cell_area;
+    let max_speed = velocity
+        .iter()
+        .map(|velocity| velocity.length())
+        .fold(0.0, f32::max);
+
+    writeln!(
+        writer,
+        "{},{},{},{},{}",
+        simulation.step_count(),
+        simulation.time(),
+        total_density,
+        kinetic_energy,
+        max_speed
+    )
+}
+
+fn exit_with_load_error(path: &Path, error: io::Error) -> ! {
+    exit_with_error(format!("Failed to load {}: {}", path.display(), error))
+}
+
+fn exit_with_scene_error(options: &Options, error: io::Error) -> ! {
+    let path = options.scene.as_deref().unwrap_or_else(|| Path::new(""));
+    exit_with_error(format!(
+        "Failed to load scene {}: {}",
+        path.display(),
+        error
+    ))
+}
+
+// Runs the simulation for the requested number of steps without a window or surface, writing the
+// outputs given on the command line, then returns. With --cpu no GPU is used at all.
+pub fn run(options: &Options) {
+    let scene = options.scene.as_ref().map(|path| {
+        Scene::load(path).unwrap_or_else(|error| exit_with_scene_error(options, error))
+    });
+    let grid_size = match (&scene, options.grid_size) {
+        (_, Some(grid_size)) => grid_size,
+        (Some(scene), None) => (scene.grid_size[0], scene.grid_size[1]),
+        (None, None) => (
+            fluid_simulator::DEFAULT_GRID_SIZE_X,
+            fluid_simulator::DEFAULT_GRID_SIZE_Y,
+        ),
+    };
+    let mut scene = scene.unwrap_or_default();
+    scene.grid_size = [grid_size.0, grid_size.1];
+    let directory = options
+        .scene
+        .as_ref()
+        .and_then(|path| path.parent())
+        .unwrap_or_else(|| Path::new(""));
+
+    if options.cpu {
+        println!("Running headless on the CPU");
+        let mut simulation = CpuSimulation::from_scene(&scene, directory)
+            .unwrap_or_else(|error| exit_with_scene_error(options, error));
+        if let Some(path) = &options.dye {
+            simulation
+                .load_density_image(path, options.dye_channel)
+                .unwrap_or_else(|error| exit_with_load_error(path, error));
+        }
+        if let Some(path) = &options.obstacle_mask {
+            simulation
+                .load_obstacle_mask(path)
+                .unwrap_or_else(|error| exit_with_load_error(path, error));
+        }
+        run_steps(options, &mut simulation, grid_size, |_| ());
+        return;
+    }
+
+    let (device, queue) = create_device(options);
+    let mut fluid_simulator =
+        FluidSimulator::with_device(device, queue, FRAME_FORMAT, grid_size.0, grid_size.1);
+    if options.scene.is_some() {
+        scene
+            .apply(&mut fluid_simulator, directory)
+            .unwrap_or_else(|error| exit_with_scene_error(options, error));
+    }
+    if let Some(path) = &options.dye {
+        fluid_simulator
+            .load_density_image(path, options.dye_channel)
+            .unwrap_or_else(|error| exit_with_load_error(path, error));
+    }
+    if let Some(path) = &options.obstacle_mask {
+        fluid_simulator
+            .load_obstacle_mask(path)
+            .unwrap_or_else(|error| exit_with_load_error(path, error));
+    }
+
+    let cpu_solver = options
+        .compare_cpu
+        .then(|| CpuSolver::from_simulator(&fluid_simulator));
+    let mut gpu = Gpu {
+        fluid_simulator,
+        cpu_solver,
+    };
+    let visualization = scene.visualization;
+    let mut frames_recorded = 0;
+    run_steps(options, &mut gpu, grid_size, |gpu| {
+        if let Some(directory) = &options.frames_dir {
+            match image_capture::save_frame(
+                &gpu.fluid_simulator,
+                visualization,
+                options.window_size.0 as u32,
+                options.window_size.1 as u32,
+                directory,
+                frames_recorded,
+            ) {
+                Ok(()) => frames_recorded += 1,
+                Err(error) => println!("Failed to record frame: {}", error),
+            }
+        }
+    });
+
+    if let Some(cpu_solver) = &gpu.cpu_solver {
+        let (velocity, density) = gpu.fields();
+        let difference = cpu_solver.difference(&velocity, &density);
+        println!(
+            "Largest difference between the GPU and the CPU: velocity {}, density {}",
+            difference.velocity, difference.density
+        );
+        if !difference.within(options.tolerance) {
+            exit_with_error(format!(
+                "The GPU and CPU fields differ by more than {}",
+                options.tolerance
+            ));
+        }
+    }
+}
+
+// Runs --steps steps, writing the outputs after each one and the snapshot at the end. after_step
+// writes the outputs only one of the simulations has.
+fn run_steps<S: Simulation>(
+    options: &Options,
+    simulation: &mut S,
+    grid_size: (usize, usize),
+    mut after_step: impl FnMut(&S),
+) {
+    let mut statistics = options.stats.as_ref().map(|path| {
+        let mut writer = File::create(path)
+            .map(BufWriter::new)
+            .unwrap_or_else(|error| {
+                exit_with_error(format!("Failed to create {}: {}", path.display(), error))
+            });
+        writeln!(writer, "step,time,total_density,kinetic_energy,max_speed")
+            .unwrap_or_else(|error| exit_with_write_error(path, error));
+        (path, writer)
+    });
+    if let Some((path, writer)) = &mut statistics {
+        write_statistics(writer, simulation, grid_size)
+            .unwrap_or_else(|error| exit_with_write_error(path, error));
+    }
+
+    let steps = options.steps.unwrap_or(0);
+    for _ in 0..steps {
+        simulation.step();
+
+        after_step(simulation);
+        if let Some(directory) = &options.vtk_dir {
+            crate::export_vtk(simulation, directory);
+        }
+        if let Some(directory) = &options.numpy_dir {
+            if options.numpy_interval > 0
+                && simulation
+                    .step_count()
+                    .is_multiple_of(options.numpy_interval)
+            {
+                crate::export_numpy(simulation, directory, true);
+            }
+        }
+        if let Some((path, writer)) = &mut statistics {
+            write_statistics(writer, simulation, grid_size)
+                .unwrap_or_else(|error| exit_with_write_error(path, error));
+        }
+    }
+    // Flushed here so a failed final write is reported instead of being dropped
+    if let Some((path, writer)) = &mut statistics {
+        writer
+            .flush()
+            .unwrap_or_else(|error| exit_with_write_error(path, error));
+    }
+
+    if let Some(path) = &options.snapshot {
+        if let Err(error) = simulation.save_snapshot(path) {
+            exit_with_error(format!(
+                "Failed to save snapshot {}: {}",
+                path.display(),
+                error
+            ));
+        }
+    }
+    println!("Ran {} steps", steps);
+}
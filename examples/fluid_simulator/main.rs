@@ -0,0 +1,1100 @@
+use std::{collections::VecDeque, io, path::Path, sync::Arc, time::Instant};
+
+use clap::Parser;
+use egui_winit_platform::{Platform, PlatformDescriptor};
+use glam::UVec2;
+use winit::{dpi::LogicalSize, event::Event::*, event_loop::ControlFlow};
+
+mod cli;
+mod headless;
+mod verification;
+
+use fluid_simulator::{
+    cpu_solver::CpuSimulation,
+    diagnostics, image_capture,
+    image_import::Channel,
+    obstacles::{Motion, Obstacle, Shape},
+    profiler,
+    scenarios::Scenario,
+    scene::Scene,
+    FluidSimulator, ForceField, RunState, Visualization, MAX_FORCE_FIELDS,
+};
+
+// Returns whether the value was changed
+fn vec2_ui(ui: &mut egui::Ui, value: &mut glam::Vec2, speed: f32) -> bool {
+    ui.horizontal(|ui| {
+        let x = ui.add(egui::DragValue::new(&mut value.x).speed(speed).prefix("x:"));
+        let y = ui.add(egui::DragValue::new(&mut value.y).speed(speed).prefix("y:"));
+        x.changed() || y.changed()
+    })
+    .inner
+}
+
+fn force_fields_ui(ui: &mut egui::Ui, force_fields: &mut Vec<ForceField>) {
+    let mut removed = None;
+    for (index, field) in force_fields.iter_mut().enumerate() {
+        ui.group(|ui| {
+            match field {
+                ForceField::Radial {
+                    center,
+                    radius,
+                    strength,
+                }
+                | ForceField::Vortex {
+                    center,
+                    radius,
+                    strength,
+                } => {
+                    ui.label("center");
+                    vec2_ui(ui, center, 0.01);
+                    ui.add(
+                        egui::DragValue::new(radius)
+                            .speed(0.01)
+                            .clamp_range(0.0..=1.0)
+                            .prefix("radius:"),
+                    );
+                    ui.add(
+                        egui::DragValue::new(strength)
+                            .speed(0.05)
+                            .prefix("strength:"),
+                    );
+                }
+                ForceField::Wind { min, max, force } => {
+                    ui.label("min");
+                    vec2_ui(ui, min, 0.01);
+                    ui.label("max");
+                    vec2_ui(ui, max, 0.01);
+                    ui.label("force");
+                    vec2_ui(ui, force, 0.05);
+                }
+            }
+            if ui.button("Remove").clicked() {
+                removed = Some(index);
+            }
+        });
+    }
+    if let Some(index) = removed {
+        force_fields.remove(index);
+    }
+
+    // The force pass only evaluates the first MAX_FORCE_FIELDS fields
+    let full = force_fields.len() >= MAX_FORCE_FIELDS;
+    ui.horizontal(|ui| {
+        ui.set_enabled(!full);
+        if ui.button("Add Radial").clicked() {
+            force_fields.push(ForceField::Radial {
+                center: glam::vec2(0.5, 0.5),
+                radius: 0.25,
+                strength: 1.0,
+            });
+        }
+        if ui.button("Add Vortex").clicked() {
+            force_fields.push(ForceField::Vortex {
+                center: glam::vec2(0.5, 0.5),
+                radius: 0.25,
+                strength: 1.0,
+            });
+        }
+        if ui.button("Add Wind").clicked() {
+            force_fields.push(ForceField::Wind {
+                min: glam::vec2(0.0, 0.0),
+                max: glam::vec2(1.0, 0.25),
+                force: glam::vec2(1.0, 0.0),
+            });
+        }
+    });
+    if full {
+        ui.label(format!("At most {} force fields", MAX_FORCE_FIELDS));
+    }
+}
+
+fn diagnostics_ui(ui: &mut egui::Ui, history: &VecDeque<diagnostics::Sample>) {
+    let latest = match history.back() {
+        Some(latest) => latest,
+        None => {
+            ui.label("Run a step to collect diagnostics");
+            return;
+        }
+    };
+
+    ui.label(format!("step {}, time {:.3}", latest.step, latest.time));
+    if latest.nan_cells > 0 {
+        ui.colored_label(
+            egui::Color32::RED,
+            format!("{} cells are NaN", latest.nan_cells),
+        );
+    }
+    for (index, (name, value)) in latest.quantities().iter().enumerate() {
+        ui.label(format!("{}: {:.4e}", name, value));
+        let values = history
+            .iter()
+            .map(|sample| egui::plot::Value::new(sample.step as f64, sample.quantities()[index].1));
+        ui.add(
+            egui::plot::Plot::new(name)
+                .line(egui::plot::Line::new(egui::plot::Values::from_values_iter(
+                    values,
+                )))
+                .height(60.0)
+                .allow_zoom(false)
+                .allow_drag(false),
+        );
+    }
+}
+
+fn profiler_ui(ui: &mut egui::Ui, history: Option<&VecDeque<profiler::FrameTimings>>) {
+    let history = match history {
+        Some(history) if !history.is_empty() => history,
+        Some(_) => {
+            ui.label("Waiting for the first timings");
+            return;
+        }
+        None => {
+            ui.label("The adapter does not support timestamp queries");
+            return;
+        }
+    };
+
+    // Average over the history of every pass seen in it, in the order they were recorded
+    let mut passes: Vec<(&str, f32, usize)> = Vec::new();
+    for pass in history.iter().flat_map(|frame| &frame.passes) {
+        match passes.iter_mut().find(|(name, ..)| *name == pass.name) {
+            Some((_, total, count)) => {
+                *total += pass.milliseconds;
+                *count += 1;
+            }
+            None => passes.push((pass.name, pass.milliseconds, 1)),
+        }
+    }
+    let latest = history.back().unwrap();
+
+    egui::Grid::new("profiler_passes")
+        .striped(true)
+        .show(ui, |ui| {
+            ui.label("pass");
+            ui.label("last ms");
+            ui.label("average ms");
+            ui.end_row();
+            for (name, total, count) in &passes {
+                let last = latest.passes.iter().find(|pass| pass.name == *name);
+                ui.label(*name);
+                ui.label(last.map_or("-".to_string(), |pass| format!("{:.3}", pass.milliseconds)));
+                ui.label(format!("{:.3}", total / *count as f32));
+                ui.end_row();
+            }
+            ui.label("total");
+            ui.label(format!("{:.3}", latest.total_milliseconds()));
+            ui.end_row();
+        });
+
+    let values = history
+        .iter()
+        .enumerate()
+        .map(|(index, frame)| egui::plot::Value::new(index as f64, frame.total_milliseconds()));
+    ui.add(
+        egui::plot::Plot::new("profiler_frame_time")
+            .line(
+                egui::plot::Line::new(egui::plot::Values::from_values_iter(values)).name("GPU ms"),
+            )
+            .include_y(0.0)
+            .height(80.0)
+            .allow_zoom(false)
+            .allow_drag(false),
+    );
+}
+
+fn obstacles_ui(ui: &mut egui::Ui, fluid_simulator: &mut FluidSimulator) {
+    let mut changed = false;
+    let mut removed = None;
+    for (index, obstacle) in fluid_simulator.obstacles.iter_mut().enumerate() {
+        ui.group(|ui| {
+            match &mut obstacle.shape {
+                Shape::Circle { radius } => {
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(radius)
+                                .speed(0.01)
+                                .clamp_range(0.0..=1.0)
+                                .prefix("radius:"),
+                        )
+                        .changed();
+                }
+                Shape::Box { half_extents } => {
+                    ui.label("half extents");
+                    changed |= vec2_ui(ui, half_extents, 0.01);
+                }
+                Shape::Polygon { vertices } => {
+                    ui.label(format!("polygon with {} vertices", vertices.len()));
+                }
+                Shape::Mask { width, height, .. } => {
+                    ui.label(format!("{}x{} mask", width, height));
+                }
+            }
+            ui.label("origin");
+            changed |= vec2_ui(ui, &mut obstacle.origin, 0.01);
+            match &mut obstacle.motion {
+                Motion::Static => {
+                    ui.label("static");
+                }
+                Motion::Oscillate {
+                    amplitude,
+                    frequency,
+                } => {
+                    ui.label("amplitude");
+                    changed |= vec2_ui(ui, amplitude, 0.01);
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(frequency)
+                                .speed(0.01)
+                                .prefix("frequency:"),
+                        )
+                        .changed();
+                }
+                Motion::Orbit { radius, frequency } => {
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(radius)
+                                .speed(0.01)
+                                .prefix("orbit radius:"),
+                        )
+                        .changed();
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(frequency)
+                                .speed(0.01)
+                                .prefix("frequency:"),
+                        )
+                        .changed();
+                }
+                Motion::Rotate { angular_velocity } => {
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(angular_velocity)
+                                .speed(0.05)
+                                .prefix("angular velocity:"),
+                        )
+                        .changed();
+                }
+                Motion::Dynamic {
+                    mass,
+                    inertia,
+                    drag,
+                } => {
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(mass)
+                                .speed(0.01)
+                                .clamp_range(0.001..=100.0)
+                                .prefix("mass:"),
+                        )
+                        .changed();
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(inertia)
+                                .speed(0.001)
+                                .clamp_range(0.0001..=100.0)
+                                .prefix("inertia:"),
+                        )
+                        .changed();
+                    changed |= ui
+                        .add(egui::DragValue::new(drag).speed(0.05).prefix("drag:"))
+                        .changed();
+                }
+            }
+            if ui.button("Remove").clicked() {
+                removed = Some(index);
+            }
+        });
+    }
+    if let Some(index) = removed {
+        fluid_simulator.remove_obstacle(index);
+    }
+    if changed {
+        fluid_simulator.obstacles_changed();
+    }
+
+    ui.horizontal(|ui| {
+        if ui.button("Add Stirring Rod").clicked() {
+            fluid_simulator.add_obstacle(Obstacle::new(
+                Shape::Circle { radius: 0.05 },
+                glam::vec2(0.5, 0.5),
+                Motion::Orbit {
+                    radius: 0.2,
+                    frequency: 0.25,
+                },
+            ));
+        }
+        if ui.button("Add Paddle").clicked() {
+            fluid_simulator.add_obstacle(Obstacle::new(
+                Shape::Box {
+                    half_extents: glam::vec2(0.2, 0.03),
+                },
+                glam::vec2(0.5, 0.5),
+                Motion::Rotate {
+                    angular_velocity: 1.0,
+                },
+            ));
+        }
+        if ui.button("Add Sliding Paddle").clicked() {
+            fluid_simulator.add_obstacle(Obstacle::new(
+                Shape::Box {
+                    half_extents: glam::vec2(0.03, 0.2),
+                },
+                glam::vec2(0.5, 0.5),
+                Motion::Oscillate {
+                    amplitude: glam::vec2(0.3, 0.0),
+                    frequency: 0.2,
+                },
+            ));
+        }
+        if ui.button("Add Falling Ball").clicked() {
+            fluid_simulator.add_obstacle(Obstacle::new(
+                Shape::Circle { radius: 0.05 },
+                glam::vec2(0.5, 0.8),
+                Motion::Dynamic {
+                    mass: 0.1,
+                    inertia: 0.001,
+                    drag: 5.0,
+                },
+            ));
+        }
+        if ui.button("Add Wedge").clicked() {
+            fluid_simulator.add_obstacle(Obstacle::new(
+                Shape::Polygon {
+                    vertices: vec![
+                        glam::vec2(-0.1, -0.1),
+                        glam::vec2(0.1, 0.0),
+                        glam::vec2(-0.1, 0.1),
+                    ],
+                },
+                glam::vec2(0.5, 0.5),
+                Motion::Static,
+            ));
+        }
+    });
+}
+
+// Exports of the GPU simulator and of the CPU simulation used by headless --cpu
+trait Exports {
+    fn step_count(&self) -> u64;
+    fn export_vtk(&self, path: &Path) -> io::Result<()>;
+    fn export_npy(&self, velocity_path: &Path, density_path: &Path) -> io::Result<()>;
+    fn export_npz(&self, path: &Path) -> io::Result<()>;
+}
+
+impl Exports for FluidSimulator {
+    fn step_count(&self) -> u64 {
+        self.step_count()
+    }
+
+    fn export_vtk(&self, path: &Path) -> io::Result<()> {
+        self.export_vtk(path)
+    }
+
+    fn export_npy(&self, velocity_path: &Path, density_path: &Path) -> io::Result<()> {
+        self.export_npy(velocity_path, density_path)
+    }
+
+    fn export_npz(&self, path: &Path) -> io::Result<()> {
+        self.export_npz(path)
+    }
+}
+
+impl Exports for CpuSimulation {
+    fn step_count(&self) -> u64 {
+        self.step_count()
+    }
+
+    fn export_vtk(&self, path: &Path) -> io::Result<()> {
+        self.export_vtk(path)
+    }
+
+    fn export_npy(&self, velocity_path: &Path, density_path: &Path) -> io::Result<()> {
+        self.export_npy(velocity_path, density_path)
+    }
+
+    fn export_npz(&self, path: &Path) -> io::Result<()> {
+        self.export_npz(path)
+    }
+}
+
+// Writes one numbered .vti file per simulation step into the directory
+fn export_vtk(simulation: &impl Exports, directory: impl AsRef<Path>) {
+    let directory = directory.as_ref();
+    let path = directory.join(format!("fields_{:06}.vti", simulation.step_count()));
+    let result = std::fs::create_dir_all(directory).and_then(|_| simulation.export_vtk(&path));
+    if let Err(error) = result {
+        println!("Failed to export {}: {}", path.display(), error);
+    }
+}
+
+// Writes either one numbered .npz file with the velocity and density arrays, or a numbered .npy
+// file per field, into the directory
+fn export_numpy(simulation: &impl Exports, directory: impl AsRef<Path>, npz: bool) {
+    let directory = directory.as_ref();
+    let step = simulation.step_count();
+    let result = std::fs::create_dir_all(directory).and_then(|_| {
+        if npz {
+            simulation.export_npz(&directory.join(format!("fields_{:06}.npz", step)))
+        } else {
+            simulation.export_npy(
+                &directory.join(format!("velocity_{:06}.npy", step)),
+                &directory.join(format!("density_{:06}.npy", step)),
+            )
+        }
+    });
+    if let Err(error) = result {
+        println!("Failed to export into {}: {}", directory.display(), error);
+    }
+}
+
+// Text for a path field in the settings window
+fn path_text(path: Option<&Path>, default: &str) -> String {
+    path.map_or_else(|| default.to_string(), |path| path.display().to_string())
+}
+
+fn optional_path(path: &str) -> Option<&Path> {
+    if path.is_empty() {
+        None
+    } else {
+        Some(Path::new(path))
+    }
+}
+
+// Loads the scene into the simulator, replacing the simulator when the scene has a different grid
+// size. The grid size of the scene can be overridden. Returns the scene.
+fn load_scene(
+    path: &Path,
+    grid_size: Option<(usize, usize)>,
+    fluid_simulator: &mut FluidSimulator,
+    renderer: &rend3::Renderer,
+    surface_format: wgpu::TextureFormat,
+) -> std::io::Result<Scene> {
+    let mut scene = Scene::load(path)?;
+    if let Some((grid_size_x, grid_size_y)) = grid_size {
+        scene.grid_size = [grid_size_x, grid_size_y];
+    }
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    if scene.grid_size == <[usize; 2]>::from(fluid_simulator.grid_size()) {
+        scene.apply(fluid_simulator, directory)?;
+    } else {
+        *fluid_simulator =
+            scene.build(&renderer.device, &renderer.queue, surface_format, directory)?;
+    }
+    Ok(scene)
+}
+
+fn main() {
+    let options = cli::Options::parse();
+    if options.headless {
+        headless::run(&options);
+        return;
+    }
+    if options.verify {
+        verification::run(&options);
+        return;
+    }
+
+    // Create event loop and window
+    let event_loop = winit::event_loop::EventLoop::new();
+    let window = {
+        let mut builder = winit::window::WindowBuilder::new();
+        builder = builder.with_title("Fluid Simulator");
+        builder = builder.with_inner_size(LogicalSize {
+            width: options.window_size.0 as u32,
+            height: options.window_size.1 as u32,
+        });
+        builder.build(&event_loop).expect("Could not build window")
+    };
+
+    let window_size = window.inner_size();
+
+    // Create the Instance, Adapter, and Device. We can specify preferred backend, device name, or rendering mode. Anything not given on the command line is left for rend3 to choose.
+    let iad = futures::executor::block_on(rend3::create_iad(
+        options.backend.map(wgpu::Backend::from),
+        options.adapter.clone(),
+        None,
+    ))
+    .unwrap();
+    // rend3 picks the device features, the GPU profiler only runs if they include timestamp queries
+    let timestamp_query = wgpu::Features::TIMESTAMP_QUERY;
+    if iad.adapter.features().contains(timestamp_query)
+        && !iad.device.features().contains(timestamp_query)
+    {
+        println!("The renderer did not enable timestamp queries, the GPU profiler is disabled");
+    }
+    let present_mode = rend3::types::PresentMode::from(options.present_mode);
+
+    // The one line of unsafe needed. We just need to guarentee that the window outlives the use of the surface.
+    let surface = unsafe { Arc::new(iad.instance.create_surface(&window)) };
+    // Get the preferred format for the surface.
+    let format = surface.get_preferred_format(&iad.adapter).unwrap();
+    // Configure the surface to be ready for rendering.
+    rend3::configure_surface(
+        &surface,
+        &iad.device,
+        format,
+        UVec2::new(window_size.width, window_size.height),
+        present_mode,
+    );
+
+    // Make us a renderer.
+    let renderer = rend3::Renderer::new(
+        iad,
+        Some(window_size.width as f32 / window_size.height as f32),
+    )
+    .unwrap();
+
+    // Create the egui render egui_routine
+    let mut egui_routine = rend3_egui::EguiRenderRoutine::new(
+        &renderer,
+        format,
+        1, // For now this has to be 1, until rendergraphs support multisampling
+        window_size.width,
+        window_size.height,
+        window.scale_factor() as f32,
+    );
+
+    let (grid_size_x, grid_size_y) = options.grid_size.unwrap_or((
+        fluid_simulator::DEFAULT_GRID_SIZE_X,
+        fluid_simulator::DEFAULT_GRID_SIZE_Y,
+    ));
+    let mut fluid_simulator_routine =
+        FluidSimulator::new(&renderer, format, grid_size_x, grid_size_y);
+    let mut show_velocity_field = false;
+    // Scenario the fields were initialized from, None when they come from a snapshot or a scene
+    // without one
+    let mut scenario = Some(Scenario::Empty);
+    if let Some(path) = &options.scene {
+        match load_scene(
+            path,
+            options.grid_size,
+            &mut fluid_simulator_routine,
+            &renderer,
+            format,
+        ) {
+            Ok(scene) => {
+                show_velocity_field = scene.visualization == Visualization::Velocity;
+                scenario = scene.initial_fields.scenario;
+            }
+            Err(error) => println!("Failed to load scene {}: {}", path.display(), error),
+        }
+    }
+    if let Some(path) = &options.dye {
+        if let Err(error) = fluid_simulator_routine.load_density_image(path, options.dye_channel) {
+            println!("Failed to load {}: {}", path.display(), error);
+        }
+    }
+    if let Some(path) = &options.obstacle_mask {
+        if let Err(error) = fluid_simulator_routine.load_obstacle_mask(path) {
+            println!("Failed to load {}: {}", path.display(), error);
+        }
+    }
+    if options.paused {
+        fluid_simulator_routine.run_state = RunState::Paused;
+    }
+
+    let camera_pitch = std::f32::consts::FRAC_PI_4;
+    let camera_yaw = -std::f32::consts::FRAC_PI_4;
+    // These values may seem arbitrary, but they center the camera on the cube in the scene
+    let camera_location = glam::Vec3A::new(5.0, 7.5, -5.0);
+    let view = glam::Mat4::from_euler(glam::EulerRot::XYZ, -camera_pitch, -camera_yaw, 0.0);
+    let view = view * glam::Mat4::from_translation((-camera_location).into());
+
+    // Set camera location data
+    renderer.set_camera_data(rend3::types::Camera {
+        projection: rend3::types::CameraProjection::Perspective {
+            vfov: 60.0,
+            near: 0.1,
+        },
+        view,
+    });
+
+    // We use the egui_winit_platform crate as the platform.
+    let mut platform = Platform::new(PlatformDescriptor {
+        physical_width: window_size.width as u32,
+        physical_height: window_size.height as u32,
+        scale_factor: window.scale_factor(),
+        font_definitions: egui::FontDefinitions::default(),
+        style: Default::default(),
+    });
+
+    let start_time = Instant::now();
+
+    let mut cursor_position = glam::Vec2::ZERO;
+    let mut cursor_in_window = false;
+    let mut dragged_obstacle: Option<usize> = None;
+    let mut steps_to_run = 10;
+    let mut scene_path = path_text(options.scene.as_deref(), "scene.ron");
+    let mut snapshot_path = path_text(options.snapshot.as_deref(), "snapshot.fsim");
+    let mut vtk_directory = path_text(options.vtk_dir.as_deref(), "vtk");
+    let mut export_vtk_every_step = options.vtk_dir.is_some();
+    let mut numpy_directory = path_text(options.numpy_dir.as_deref(), "numpy");
+    let mut numpy_export_interval = if options.numpy_dir.is_some() {
+        options.numpy_interval
+    } else {
+        0
+    };
+    let mut numpy_export_npz = true;
+    let mut numpy_velocity_path = String::new();
+    let mut numpy_density_path = String::new();
+    let mut dye_image_path = path_text(options.dye.as_deref(), "");
+    let mut dye_channel = options.dye_channel;
+    let mut obstacle_mask_path = path_text(options.obstacle_mask.as_deref(), "");
+    let mut recording = options.frames_dir.is_some();
+    let mut recording_directory = path_text(options.frames_dir.as_deref(), "frames");
+    // Numbers the recorded frames, unlike the step count it does not go back to zero on reset
+    let mut frames_recorded = 0;
+    let mut steps_run = 0;
+    let mut recording_size = [window_size.width, window_size.height];
+    event_loop.run(move |event, _, control_flow| {
+        // Pass the winit events to the platform integration.
+        platform.handle_event(&event);
+
+        match event {
+            RedrawRequested(..) => {
+                platform.update_time(start_time.elapsed().as_secs_f64());
+                platform.begin_frame();
+
+                let ctx = platform.context();
+                if cursor_in_window && !ctx.is_pointer_over_area() {
+                    if let Some(value) = fluid_simulator_routine.probe(cursor_position) {
+                        let obstacle = fluid_simulator_routine.obstacle_at(cursor_position);
+                        egui::show_tooltip(&ctx, egui::Id::new("cell_probe"), |ui| {
+                            ui.label(format!("cell ({}, {})", value.cell.0, value.cell.1));
+                            ui.label(format!(
+                                "velocity ({:.6}, {:.6})",
+                                value.velocity.x, value.velocity.y
+                            ));
+                            ui.label(format!("density {:.6}", value.density));
+                            ui.label(format!("divergence {:.6}", value.divergence));
+                            ui.label(format!("pressure {:.6}", value.pressure));
+                            if let Some(index) = obstacle {
+                                ui.label(format!("inside obstacle {}", index));
+                            }
+                            ui.label(format!("at step {}", value.step));
+                        });
+                    }
+                }
+                egui::Window::new("Settings")
+                    .resizable(true)
+                    .show(&ctx, |ui| {
+                        let previous_scenario = scenario;
+                        egui::ComboBox::from_label("Scenario")
+                            .selected_text(scenario.map_or("Custom", |scenario| scenario.name()))
+                            .show_ui(ui, |ui| {
+                                for option in Scenario::ALL {
+                                    ui.selectable_value(&mut scenario, Some(option), option.name());
+                                }
+                            });
+                        if scenario != previous_scenario {
+                            if let Some(scenario) = scenario {
+                                scenario.apply(&mut fluid_simulator_routine);
+                            }
+                        }
+
+                        ui.horizontal(|ui| {
+                            let pause_label =
+                                if fluid_simulator_routine.run_state == RunState::Running {
+                                    "Pause"
+                                } else {
+                                    "Resume"
+                                };
+                            if ui.button(pause_label).clicked() {
+                                fluid_simulator_routine.toggle_pause();
+                            }
+                            if ui.button("Step").clicked() {
+                                fluid_simulator_routine.step(1);
+                            }
+                            if ui.button("Step N").clicked() {
+                                fluid_simulator_routine.step(steps_to_run);
+                            }
+                            ui.add(
+                                egui::DragValue::new(&mut steps_to_run)
+                                    .clamp_range(1..=10000)
+                                    .prefix("N:"),
+                            );
+                            if ui.button("Reset").clicked() {
+                                fluid_simulator_routine.reset();
+                            }
+                        });
+                        ui.label("Space: pause, Right: step, N: step N, R: reset");
+
+                        ui.horizontal(|ui| {
+                            ui.text_edit_singleline(&mut scene_path);
+                            if ui.button("Save Scene").clicked() {
+                                let visualization = if show_velocity_field {
+                                    Visualization::Velocity
+                                } else {
+                                    Visualization::Density
+                                };
+                                if let Err(error) =
+                                    Scene::from_simulator(&fluid_simulator_routine, visualization)
+                                        .save(&scene_path)
+                                {
+                                    println!("Failed to save scene {}: {}", scene_path, error);
+                                }
+                            }
+                            if ui.button("Load Scene").clicked() {
+                                match load_scene(
+                                    Path::new(&scene_path),
+                                    None,
+                                    &mut fluid_simulator_routine,
+                                    &renderer,
+                                    format,
+                                ) {
+                                    Ok(scene) => {
+                                        show_velocity_field =
+                                            scene.visualization == Visualization::Velocity;
+                                        scenario = scene.initial_fields.scenario;
+                                        dragged_obstacle = None;
+                                    }
+                                    Err(error) => {
+                                        println!("Failed to load scene {}: {}", scene_path, error)
+                                    }
+                                }
+                            }
+                        });
+
+                        ui.horizontal(|ui| {
+                            ui.text_edit_singleline(&mut snapshot_path);
+                            if ui.button("Save Snapshot").clicked() {
+                                if let Err(error) =
+                                    fluid_simulator_routine.save_snapshot(&snapshot_path)
+                                {
+                                    println!(
+                                        "Failed to save snapshot {}: {}",
+                                        snapshot_path, error
+                                    );
+                                }
+                            }
+                            if ui.button("Load Snapshot").clicked() {
+                                match fluid_simulator_routine.load_snapshot(&snapshot_path) {
+                                    Ok(()) => scenario = None,
+                                    Err(error) => println!(
+                                        "Failed to load snapshot {}: {}",
+                                        snapshot_path, error
+                                    ),
+                                }
+                            }
+                        });
+
+                        ui.horizontal(|ui| {
+                            ui.text_edit_singleline(&mut vtk_directory);
+                            if ui.button("Export VTK").clicked() {
+                                export_vtk(&fluid_simulator_routine, &vtk_directory);
+                            }
+                            ui.checkbox(&mut export_vtk_every_step, "Every step");
+                        });
+
+                        ui.horizontal(|ui| {
+                            ui.text_edit_singleline(&mut numpy_directory);
+                            if ui.button("Export NumPy").clicked() {
+                                export_numpy(
+                                    &fluid_simulator_routine,
+                                    &numpy_directory,
+                                    numpy_export_npz,
+                                );
+                            }
+                            ui.add(
+                                egui::DragValue::new(&mut numpy_export_interval)
+                                    .clamp_range(0..=10000)
+                                    .prefix("every N steps:"),
+                            );
+                            ui.checkbox(&mut numpy_export_npz, "npz");
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("velocity .npy");
+                            ui.text_edit_singleline(&mut numpy_velocity_path);
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("density .npy");
+                            ui.text_edit_singleline(&mut numpy_density_path);
+                        });
+                        if ui.button("Load Initial Conditions").clicked() {
+                            if let Err(error) = fluid_simulator_routine.load_npy_initial_conditions(
+                                optional_path(&numpy_velocity_path),
+                                optional_path(&numpy_density_path),
+                            ) {
+                                println!("Failed to load initial conditions: {}", error);
+                            }
+                        }
+
+                        ui.horizontal(|ui| {
+                            ui.label("dye .png");
+                            ui.text_edit_singleline(&mut dye_image_path);
+                            egui::ComboBox::from_id_source("dye_channel")
+                                .selected_text(dye_channel.name())
+                                .show_ui(ui, |ui| {
+                                    for channel in Channel::ALL {
+                                        ui.selectable_value(
+                                            &mut dye_channel,
+                                            channel,
+                                            channel.name(),
+                                        );
+                                    }
+                                });
+                            if ui.button("Load").clicked() {
+                                if let Err(error) = fluid_simulator_routine
+                                    .load_density_image(&dye_image_path, dye_channel)
+                                {
+                                    println!("Failed to load {}: {}", dye_image_path, error);
+                                }
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("obstacle mask .png");
+                            ui.text_edit_singleline(&mut obstacle_mask_path);
+                            if ui.button("Load").clicked() {
+                                if let Err(error) =
+                                    fluid_simulator_routine.load_obstacle_mask(&obstacle_mask_path)
+                                {
+                                    println!("Failed to load {}: {}", obstacle_mask_path, error);
+                                }
+                            }
+                        });
+
+                        ui.horizontal(|ui| {
+                            ui.checkbox(&mut recording, "Record PNG");
+                            ui.text_edit_singleline(&mut recording_directory);
+                        });
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::DragValue::new(&mut recording_size[0])
+                                    .clamp_range(1..=8192)
+                                    .prefix("width:"),
+                            );
+                            ui.add(
+                                egui::DragValue::new(&mut recording_size[1])
+                                    .clamp_range(1..=8192)
+                                    .prefix("height:"),
+                            );
+                        });
+
+                        ui.checkbox(&mut &mut show_velocity_field, "Visuzlize Velocity");
+                        if show_velocity_field {
+                            ui.add(
+                                egui::DragValue::new(
+                                    &mut fluid_simulator_routine.forced_velocity.x,
+                                )
+                                .speed(0.05)
+                                .clamp_range(-1.0..=1.0)
+                                .prefix("x:"),
+                            );
+                            ui.add(
+                                egui::DragValue::new(
+                                    &mut fluid_simulator_routine.forced_velocity.y,
+                                )
+                                .speed(0.05)
+                                .clamp_range(-1.0..=1.0)
+                                .prefix("y:"),
+                            );
+                        }
+
+                        ui.add(
+                            egui::DragValue::new(&mut fluid_simulator_routine.forced_density)
+                                .speed(0.05)
+                                .clamp_range(0.0..=1.0)
+                                .prefix("density:"),
+                        );
+
+                        ui.label("Dissipation");
+                        ui.add(
+                            egui::DragValue::new(&mut fluid_simulator_routine.velocity_dissipation)
+                                .speed(0.01)
+                                .clamp_range(0.0..=10.0)
+                                .prefix("velocity:"),
+                        );
+                        ui.add(
+                            egui::DragValue::new(&mut fluid_simulator_routine.density_dissipation)
+                                .speed(0.01)
+                                .clamp_range(0.0..=10.0)
+                                .prefix("density:"),
+                        );
+
+                        ui.label("Gravity");
+                        vec2_ui(ui, &mut fluid_simulator_routine.gravity, 0.05);
+
+                        egui::CollapsingHeader::new("Force Fields").show(ui, |ui| {
+                            force_fields_ui(ui, &mut fluid_simulator_routine.force_fields);
+                        });
+
+                        egui::CollapsingHeader::new("Obstacles").show(ui, |ui| {
+                            obstacles_ui(ui, &mut fluid_simulator_routine);
+                        });
+
+                        egui::CollapsingHeader::new("Diagnostics").show(ui, |ui| {
+                            diagnostics_ui(ui, fluid_simulator_routine.diagnostics());
+                        });
+
+                        egui::CollapsingHeader::new("GPU Profiler").show(ui, |ui| {
+                            profiler_ui(ui, fluid_simulator_routine.profiler_history());
+                        });
+                    });
+
+                // End the UI frame. Now let's draw the UI with our Backend, we could also handle the output here
+                let (_output, paint_commands) = platform.end_frame(Some(&window));
+                let paint_jobs = platform.context().tessellate(paint_commands);
+
+                let input = rend3_egui::Input {
+                    clipped_meshes: &paint_jobs,
+                    context: platform.context(),
+                };
+
+                // Get a frame
+                let frame = rend3::util::output::OutputFrame::Surface {
+                    surface: Arc::clone(&surface),
+                };
+
+                // Ready up the renderer
+                let (cmd_bufs, ready) = renderer.ready();
+
+                let run_step = fluid_simulator_routine.consume_step();
+                if run_step {
+                    fluid_simulator_routine.update();
+                }
+
+                fluid_simulator_routine.begin_profiling_frame();
+
+                // Build a rendergraph
+                let mut graph = rend3::RenderGraph::new();
+
+                if run_step {
+                    fluid_simulator_routine.add_forces_in_field_to_graph(&mut graph);
+                }
+                if show_velocity_field {
+                    fluid_simulator_routine.add_velocity_visualization_to_graph(&mut graph);
+                } else {
+                    fluid_simulator_routine.add_density_visualization_to_graph(&mut graph);
+                }
+
+                // Add egui on top of all the other passes
+                egui_routine.add_to_graph(&mut graph, input);
+
+                // Dispatch a render using the built up rendergraph!
+                graph.execute(&renderer, frame, cmd_bufs, &ready);
+                fluid_simulator_routine.poll_diagnostics();
+                fluid_simulator_routine.end_profiling_frame();
+
+                if run_step && recording {
+                    let visualization = if show_velocity_field {
+                        Visualization::Velocity
+                    } else {
+                        Visualization::Density
+                    };
+                    match image_capture::save_frame(
+                        &fluid_simulator_routine,
+                        visualization,
+                        recording_size[0],
+                        recording_size[1],
+                        &recording_directory,
+                        frames_recorded,
+                    ) {
+                        Ok(()) => frames_recorded += 1,
+                        Err(error) => println!("Failed to record frame: {}", error),
+                    }
+                }
+                if run_step && export_vtk_every_step {
+                    export_vtk(&fluid_simulator_routine, &vtk_directory);
+                }
+                if run_step
+                    && numpy_export_interval > 0
+                    && fluid_simulator_routine
+                        .step_count()
+                        .is_multiple_of(numpy_export_interval)
+                {
+                    export_numpy(&fluid_simulator_routine, &numpy_directory, numpy_export_npz);
+                }
+
+                *control_flow = ControlFlow::Poll;
+
+                if run_step {
+                    steps_run += 1;
+                    if options.steps == Some(steps_run) {
+                        if let Some(path) = &options.snapshot {
+                            if let Err(error) = fluid_simulator_routine.save_snapshot(path) {
+                                println!("Failed to save snapshot {}: {}", path.display(), error);
+                            }
+                        }
+                        *control_flow = ControlFlow::Exit;
+                    }
+                }
+            }
+            MainEventsCleared => {
+                window.request_redraw();
+            }
+            WindowEvent { event, .. } => match event {
+                winit::event::WindowEvent::Resized(size) => {
+                    let size = UVec2::new(size.width, size.height);
+                    // Reconfigure the surface for the new size.
+                    rend3::configure_surface(
+                        &surface,
+                        &renderer.device,
+                        format,
+                        UVec2::new(size.x, size.y),
+                        present_mode,
+                    );
+
+                    renderer.set_aspect_ratio(size.x as f32 / size.y as f32);
+
+                    egui_routine.resize(size.x, size.y, window.scale_factor() as f32);
+                }
+                winit::event::WindowEvent::CursorMoved { position, .. } => {
+                    cursor_in_window = true;
+                    let size = window.inner_size();
+                    // Grid space has its origin at the bottom left corner of the window
+                    cursor_position = glam::vec2(
+                        position.x as f32 / size.width as f32,
+                        1.0 - position.y as f32 / size.height as f32,
+                    );
+                    if let Some(index) = dragged_obstacle {
+                        if let Some(obstacle) = fluid_simulator_routine.obstacles.get_mut(index) {
+                            obstacle.drag_target = Some(cursor_position);
+                        }
+                    }
+                }
+                winit::event::WindowEvent::CursorLeft { .. } => {
+                    cursor_in_window = false;
+                }
+                winit::event::WindowEvent::MouseInput {
+                    state,
+                    button: winit::event::MouseButton::Left,
+                    ..
+                } => match state {
+                    winit::event::ElementState::Pressed => {
+                        if !platform.context().wants_pointer_input() {
+                            dragged_obstacle = fluid_simulator_routine.obstacle_at(cursor_position);
+                        }
+                    }
+                    winit::event::ElementState::Released => {
+                        if let Some(index) = dragged_obstacle.take() {
+                            if let Some(obstacle) = fluid_simulator_routine.obstacles.get_mut(index)
+                            {
+                                obstacle.drag_target = None;
+                            }
+                        }
+                    }
+                },
+                winit::event::WindowEvent::KeyboardInput {
+                    input:
+                        winit::event::KeyboardInput {
+                            state: winit::event::ElementState::Pressed,
+                            virtual_keycode: Some(key),
+                            ..
+                        },
+                    ..
+                } if !platform.context().wants_keyboard_input() => match key {
+                    winit::event::VirtualKeyCode::Space => fluid_simulator_routine.toggle_pause(),
+                    winit::event::VirtualKeyCode::Right => fluid_simulator_routine.step(1),
+                    winit::event::VirtualKeyCode::N => fluid_simulator_routine.step(steps_to_run),
+                    winit::event::VirtualKeyCode::R => fluid_simulator_routine.reset(),
+                    _ => {}
+                },
+                winit::event::WindowEvent::CloseRequested => {
+                    *control_flow = ControlFlow::Exit;
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    });
+}
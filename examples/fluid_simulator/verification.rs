@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use fluid_simulator::{
+    verification::{ErrorNorms, Flow},
+    FluidSimulator,
+};
+
+use crate::{cli::Options, headless};
+
+// Simulated time every run covers
+const END_TIME: f32 = 1.0;
+// Errors below this are rounding noise, observed orders are not computed from them
+const ROUNDING_ERROR: f32 = 1e-5;
+
+const GRID_SIZES: [usize; 4] = [16, 32, 64, 128];
+// Time step times the peak velocity over the cell size in the grid refinement, the velocity of the
+// flows is at most one
+const COURANT_NUMBER: f32 = 0.25;
+const TIME_STEPS: [f32; 4] = [1.0 / 8.0, 1.0 / 16.0, 1.0 / 32.0, 1.0 / 64.0];
+const TIME_REFINEMENT_GRID_SIZE: usize = 32;
+
+fn run_flow(
+    device: &Arc<wgpu::Device>,
+    queue: &Arc<wgpu::Queue>,
+    flow: Flow,
+    grid_size: usize,
+    time_step: f32,
+) -> ErrorNorms {
+    let mut simulator = FluidSimulator::with_device(
+        Arc::clone(device),
+        Arc::clone(queue),
+        wgpu::TextureFormat::Rgba8Unorm,
+        grid_size,
+        grid_size,
+    );
+    simulator.set_parameters(&flow.parameters(time_step));
+    simulator.force_fields.clear();
+    simulator.obstacles.clear();
+    let (velocity, density) = flow.initial_fields(grid_size, grid_size);
+    simulator.set_initial_conditions(velocity, density);
+
+    let steps = (END_TIME / time_step).round() as u32;
+    for _ in 0..steps {
+        simulator.run_step();
+    }
+    ErrorNorms::new(&flow.errors(
+        grid_size,
+        grid_size,
+        simulator.time(),
+        &simulator.read_velocity_field(),
+        &simulator.read_density_field(),
+    ))
+}
+
+// Prints one row per refinement level, with the order observed from the L2 error of the level
+// before it
+fn print_refinement(label: &str, levels: &[(String, ErrorNorms)]) {
+    println!(
+        "  {:<12} {:>12} {:>12} {:>12} {:>8}",
+        label, "L1", "L2", "Linf", "order"
+    );
+    for (index, (level, norms)) in levels.iter().enumerate() {
+        let order = match index.checked_sub(1).map(|previous| &levels[previous].1) {
+            Some(previous) if previous.l2 > ROUNDING_ERROR && norms.l2 > ROUNDING_ERROR => {
+                format!("{:.2}", norms.observed_order(previous))
+            }
+            _ => "-".to_string(),
+        };
+        println!(
+            "  {:<12} {:>12.4e} {:>12.4e} {:>12.4e} {:>8}",
+            level, norms.l1, norms.l2, norms.linf, order
+        );
+    }
+}
+
+// Runs every flow with known solution at several grid sizes or time steps and prints the error
+// norms at the end time together with the observed order of accuracy. Both refinements halve the
+// cell size or the time step from one level to the next.
+pub fn run(options: &Options) {
+    let (device, queue) = headless::create_device(options);
+
+    for flow in Flow::ALL {
+        if flow.refines_grid() {
+            println!(
+                "{}, grid refinement with a Courant number of {}",
+                flow.name(),
+                COURANT_NUMBER
+            );
+            let levels: Vec<_> = GRID_SIZES
+                .iter()
+                .map(|&grid_size| {
+                    let time_step = COURANT_NUMBER / grid_size as f32;
+                    (
+                        format!("{0}x{0}", grid_size),
+                        run_flow(&device, &queue, flow, grid_size, time_step),
+                    )
+                })
+                .collect();
+            print_refinement("grid", &levels);
+        } else {
+            println!(
+                "{}, time step refinement on a {1}x{1} grid",
+                flow.name(),
+                TIME_REFINEMENT_GRID_SIZE
+            );
+            let levels: Vec<_> = TIME_STEPS
+                .iter()
+                .map(|&time_step| {
+                    (
+                        format!("1/{}", (1.0 / time_step).round()),
+                        run_flow(&device, &queue, flow, TIME_REFINEMENT_GRID_SIZE, time_step),
+                    )
+                })
+                .collect();
+            print_refinement("time step", &levels);
+        }
+        println!();
+    }
+}
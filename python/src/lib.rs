@@ -0,0 +1,282 @@
+// Python bindings for driving the simulator from scripts and notebooks. The simulator runs
+// headless on its own device with the same GPU solver as the interactive app. Fields are float32
+// NumPy arrays indexed [y, x], with row 0 at the bottom of the domain, and velocity has a last
+// axis of size 2.
+
+use std::path::{Path, PathBuf};
+
+use glam::{vec2, Vec2};
+use numpy::{IntoPyArray, PyArray2, PyArray3, PyReadonlyArray2, PyReadonlyArray3};
+use pyo3::{
+    exceptions::{PyRuntimeError, PyValueError},
+    prelude::*,
+};
+use simulator::{
+    device, scene::Scene, FluidSimulator, ForceField, MAX_FORCE_FIELDS, MAX_GRID_SIZE,
+};
+
+// Only used by the visualization pipelines, which the bindings do not draw with
+const SURFACE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+fn create_device(
+    adapter: Option<&str>,
+) -> PyResult<(std::sync::Arc<wgpu::Device>, std::sync::Arc<wgpu::Queue>)> {
+    let (device, queue, _info) = device::create_headless_device(wgpu::Backends::PRIMARY, adapter)
+        .map_err(|error| PyRuntimeError::new_err(error.to_string()))?;
+    Ok((device, queue))
+}
+
+#[pyclass(name = "Simulator")]
+struct PySimulator {
+    simulator: FluidSimulator,
+}
+
+impl PySimulator {
+    fn add_force_field(&mut self, field: ForceField) -> PyResult<()> {
+        if self.simulator.force_fields.len() == MAX_FORCE_FIELDS {
+            return Err(PyValueError::new_err(format!(
+                "at most {} force fields are supported",
+                MAX_FORCE_FIELDS
+            )));
+        }
+        self.simulator.force_fields.push(field);
+        Ok(())
+    }
+}
+
+#[pymethods]
+impl PySimulator {
+    // adapter picks the first GPU whose name contains it
+    #[new]
+    #[args(
+        grid_size = "(simulator::DEFAULT_GRID_SIZE_X, simulator::DEFAULT_GRID_SIZE_Y)",
+        adapter = "None"
+    )]
+    fn new(grid_size: (usize, usize), adapter: Option<&str>) -> PyResult<Self> {
+        if !(1..=MAX_GRID_SIZE).contains(&grid_size.0)
+            || !(1..=MAX_GRID_SIZE).contains(&grid_size.1)
+        {
+            return Err(PyValueError::new_err(format!(
+                "grid size must be between 1 and {} in each dimension, got {:?}",
+                MAX_GRID_SIZE, grid_size
+            )));
+        }
+        let (device, queue) = create_device(adapter)?;
+        Ok(Self {
+            simulator: FluidSimulator::with_device(
+                device,
+                queue,
+                SURFACE_FORMAT,
+                grid_size.0,
+                grid_size.1,
+            ),
+        })
+    }
+
+    // Builds the simulator from a RON or TOML scene file, the same ones the app loads
+    #[staticmethod]
+    #[args(adapter = "None")]
+    fn from_scene(path: PathBuf, adapter: Option<&str>) -> PyResult<Self> {
+        let scene = Scene::load(&path)?;
+        let (device, queue) = create_device(adapter)?;
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        Ok(Self {
+            simulator: scene.build(&device, &queue, SURFACE_FORMAT, directory)?,
+        })
+    }
+
+    #[getter]
+    fn grid_size(&self) -> (usize, usize) {
+        self.simulator.grid_size()
+    }
+
+    #[getter]
+    fn step_count(&self) -> u64 {
+        self.simulator.step_count()
+    }
+
+    #[getter]
+    fn time(&self) -> f32 {
+        self.simulator.time()
+    }
+
+    #[getter]
+    fn time_step(&self) -> f32 {
+        self.simulator.time_step
+    }
+
+    #[setter]
+    fn set_time_step(&mut self, value: f32) {
+        self.simulator.time_step = value;
+    }
+
+    #[getter]
+    fn forced_velocity(&self) -> (f32, f32) {
+        self.simulator.forced_velocity.into()
+    }
+
+    #[setter]
+    fn set_forced_velocity(&mut self, value: (f32, f32)) {
+        self.simulator.forced_velocity = value.into();
+    }
+
+    #[getter]
+    fn forced_density(&self) -> f32 {
+        self.simulator.forced_density
+    }
+
+    #[setter]
+    fn set_forced_density(&mut self, value: f32) {
+        self.simulator.forced_density = value;
+    }
+
+    #[getter]
+    fn velocity_dissipation(&self) -> f32 {
+        self.simulator.velocity_dissipation
+    }
+
+    #[setter]
+    fn set_velocity_dissipation(&mut self, value: f32) {
+        self.simulator.velocity_dissipation = value;
+    }
+
+    #[getter]
+    fn density_dissipation(&self) -> f32 {
+        self.simulator.density_dissipation
+    }
+
+    #[setter]
+    fn set_density_dissipation(&mut self, value: f32) {
+        self.simulator.density_dissipation = value;
+    }
+
+    #[getter]
+    fn gravity(&self) -> (f32, f32) {
+        self.simulator.gravity.into()
+    }
+
+    #[setter]
+    fn set_gravity(&mut self, value: (f32, f32)) {
+        self.simulator.gravity = value.into();
+    }
+
+    // Force fields are the momentum emitters. Positions are in grid space, from (0, 0) to (1, 1).
+    fn add_radial_force(&mut self, center: (f32, f32), radius: f32, strength: f32) -> PyResult<()> {
+        self.add_force_field(ForceField::Radial {
+            center: center.into(),
+            radius,
+            strength,
+        })
+    }
+
+    fn add_vortex_force(&mut self, center: (f32, f32), radius: f32, strength: f32) -> PyResult<()> {
+        self.add_force_field(ForceField::Vortex {
+            center: center.into(),
+            radius,
+            strength,
+        })
+    }
+
+    fn add_wind_force(
+        &mut self,
+        min: (f32, f32),
+        max: (f32, f32),
+        force: (f32, f32),
+    ) -> PyResult<()> {
+        self.add_force_field(ForceField::Wind {
+            min: min.into(),
+            max: max.into(),
+            force: force.into(),
+        })
+    }
+
+    fn clear_force_fields(&mut self) {
+        self.simulator.force_fields.clear();
+    }
+
+    // Runs the steps without holding the GIL
+    #[args(steps = "1")]
+    fn step(&mut self, py: Python, steps: u32) {
+        let simulator = &mut self.simulator;
+        py.allow_threads(|| {
+            for _ in 0..steps {
+                simulator.run_step();
+            }
+        });
+    }
+
+    // Restores the initial fields and obstacles and sets the time back to zero
+    fn reset(&mut self) {
+        self.simulator.reset();
+    }
+
+    fn velocity<'py>(&self, py: Python<'py>) -> PyResult<&'py PyArray3<f32>> {
+        let (grid_size_x, grid_size_y) = self.simulator.grid_size();
+        let data: Vec<f32> = self
+            .simulator
+            .read_velocity_field()
+            .iter()
+            .flat_map(|velocity| [velocity.x, velocity.y])
+            .collect();
+        data.into_pyarray(py).reshape([grid_size_y, grid_size_x, 2])
+    }
+
+    fn density<'py>(&self, py: Python<'py>) -> PyResult<&'py PyArray2<f32>> {
+        let (grid_size_x, grid_size_y) = self.simulator.grid_size();
+        self.simulator
+            .read_density_field()
+            .into_pyarray(py)
+            .reshape([grid_size_y, grid_size_x])
+    }
+
+    // Overwrites the current fields. The initial fields used by reset are kept.
+    fn set_fields(
+        &mut self,
+        velocity: PyReadonlyArray3<f32>,
+        density: PyReadonlyArray2<f32>,
+    ) -> PyResult<()> {
+        let (grid_size_x, grid_size_y) = self.simulator.grid_size();
+        if velocity.shape() != [grid_size_y, grid_size_x, 2]
+            || density.shape() != [grid_size_y, grid_size_x]
+        {
+            return Err(PyValueError::new_err(format!(
+                "expected velocity of shape ({0}, {1}, 2) and density of shape ({0}, {1}), got {2:?} and {3:?}",
+                grid_size_y,
+                grid_size_x,
+                velocity.shape(),
+                density.shape()
+            )));
+        }
+
+        let velocity: Vec<Vec2> = velocity
+            .as_array()
+            .as_standard_layout()
+            .as_slice()
+            .unwrap()
+            .chunks_exact(2)
+            .map(|velocity| vec2(velocity[0], velocity[1]))
+            .collect();
+        let density = density.as_array().as_standard_layout().into_owned();
+        self.simulator
+            .write_fields(&velocity, density.as_slice().unwrap());
+        Ok(())
+    }
+
+    fn load_obstacle_mask(&mut self, path: PathBuf) -> PyResult<()> {
+        Ok(self.simulator.load_obstacle_mask(path)?)
+    }
+
+    fn save_snapshot(&self, path: PathBuf) -> PyResult<()> {
+        Ok(self.simulator.save_snapshot(path)?)
+    }
+
+    fn load_snapshot(&mut self, path: PathBuf) -> PyResult<()> {
+        Ok(self.simulator.load_snapshot(path)?)
+    }
+}
+
+#[pymodule]
+fn fluid_simulator(_py: Python, module: &PyModule) -> PyResult<()> {
+    module.add_class::<PySimulator>()?;
+    Ok(())
+}
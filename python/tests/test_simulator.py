@@ -0,0 +1,61 @@
+# Smoke tests of the bindings. Build the module into the environment with `maturin develop` first,
+# then run pytest from this directory. Tests that need a GPU are skipped when there is no adapter.
+
+import numpy as np
+import pytest
+
+from fluid_simulator import Simulator
+
+GRID_SIZE = (16, 8)
+
+
+@pytest.fixture
+def simulator():
+    try:
+        return Simulator(GRID_SIZE)
+    except RuntimeError as error:
+        pytest.skip(f"no GPU available: {error}")
+
+
+def test_rejects_empty_grid():
+    with pytest.raises(ValueError):
+        Simulator((0, 0))
+
+
+def test_field_shapes(simulator):
+    assert simulator.grid_size == GRID_SIZE
+    density = simulator.density()
+    velocity = simulator.velocity()
+    assert density.shape == (8, 16)
+    assert velocity.shape == (8, 16, 2)
+    assert density.dtype == np.float32
+    assert velocity.dtype == np.float32
+
+
+def test_step(simulator):
+    simulator.time_step = 0.1
+    simulator.forced_density = 1.0
+    simulator.density_dissipation = 0.0
+    simulator.step(3)
+    assert simulator.step_count == 3
+    assert simulator.time == pytest.approx(0.3)
+    assert np.allclose(simulator.density(), 0.3)
+
+    simulator.reset()
+    assert simulator.step_count == 0
+    assert np.allclose(simulator.density(), 0.0)
+
+
+def test_set_fields(simulator):
+    velocity = np.random.default_rng(0).random((8, 16, 2), dtype=np.float32)
+    density = np.linspace(0.0, 1.0, 8 * 16, dtype=np.float32).reshape(8, 16)
+    simulator.set_fields(velocity, density)
+    assert np.array_equal(simulator.velocity(), velocity)
+    assert np.array_equal(simulator.density(), density)
+
+    # Views that are not contiguous are copied
+    simulator.set_fields(velocity[::-1], density[::-1])
+    assert np.array_equal(simulator.density(), density[::-1])
+
+    with pytest.raises(ValueError):
+        simulator.set_fields(velocity, density.T)
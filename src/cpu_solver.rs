@@ -0,0 +1,657 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::Path,
+};
+
+use glam::{vec2, Vec2};
+
+use crate::{
+    fluid_simulator::{self, FluidSimulator, ForceField, MAX_FORCE_FIELDS},
+    image_import::{self, Channel},
+    obstacles::{self, Obstacle, ObstacleCellData, Shape},
+    scene::{Parameters, Scene},
+    snapshot::Snapshot,
+    solver::PRESSURE_ITERATIONS,
+};
+
+// CPU implementation of the simulation step, following the compute passes one operation at a time
+// so the fields can be compared against the GPU: the force pass in velocity_calculations.hlsl, then
+// advection and the pressure projection in solver.hlsl.
+pub struct CpuSolver {
+    grid_size_x: usize,
+    grid_size_y: usize,
+    pub velocity: Vec<Vec2>,
+    pub density: Vec<f32>,
+    // Pressure of the last projection, the starting guess of the next one
+    pub pressure: Vec<f32>,
+}
+
+// Largest absolute differences between two sets of fields
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FieldDifference {
+    pub velocity: f32,
+    pub density: f32,
+}
+
+impl FieldDifference {
+    pub fn within(&self, tolerance: f32) -> bool {
+        self.velocity <= tolerance && self.density <= tolerance
+    }
+}
+
+// Same as evaluate_force_field in velocity_calculations.hlsl
+fn evaluate_force_field(field: &ForceField, position: Vec2) -> Vec2 {
+    let (center, radius, strength, is_vortex) = match *field {
+        ForceField::Wind { min, max, force } => {
+            let inside = position.cmpge(min).all() && position.cmple(max).all();
+            return if inside { force } else { Vec2::ZERO };
+        }
+        ForceField::Radial {
+            center,
+            radius,
+            strength,
+        } => (center, radius, strength, false),
+        ForceField::Vortex {
+            center,
+            radius,
+            strength,
+        } => (center, radius, strength, true),
+    };
+
+    let to_center = center - position;
+    let distance = to_center.length();
+    if distance >= radius || distance == 0.0 {
+        return Vec2::ZERO;
+    }
+
+    let magnitude = strength * (1.0 - distance / radius);
+    let direction = to_center / distance;
+    if is_vortex {
+        Vec2::new(direction.y, -direction.x) * magnitude
+    } else {
+        direction * magnitude
+    }
+}
+
+// Largest element, NaN if any element is NaN so broken fields never compare as equal
+fn max_or_nan(values: impl Iterator<Item = f32>) -> f32 {
+    values.fold(0.0, |max, value| {
+        if value.is_nan() || value > max {
+            value
+        } else {
+            max
+        }
+    })
+}
+
+impl CpuSolver {
+    pub fn new(
+        grid_size_x: usize,
+        grid_size_y: usize,
+        velocity: Vec<Vec2>,
+        density: Vec<f32>,
+    ) -> Self {
+        assert_eq!(velocity.len(), grid_size_x * grid_size_y);
+        assert_eq!(density.len(), grid_size_x * grid_size_y);
+        Self {
+            grid_size_x,
+            grid_size_y,
+            velocity,
+            density,
+            pressure: vec![0.0; grid_size_x * grid_size_y],
+        }
+    }
+
+    // Starts from the fields currently on the GPU
+    pub fn from_simulator(simulator: &FluidSimulator) -> Self {
+        let (grid_size_x, grid_size_y) = simulator.grid_size();
+        let mut solver = Self::new(
+            grid_size_x,
+            grid_size_y,
+            simulator.read_velocity_field(),
+            simulator.read_density_field(),
+        );
+        solver.pressure = simulator.read_pressure_field();
+        solver
+    }
+
+    // Runs one step with the parameters, force fields and obstacles of the simulator. Like the GPU
+    // step it expects FluidSimulator::update to have been called already.
+    pub fn step(&mut self, simulator: &FluidSimulator) {
+        self.step_with(
+            &simulator.parameters(),
+            &simulator.force_fields,
+            &simulator.obstacles,
+        );
+    }
+
+    pub fn step_with(
+        &mut self,
+        parameters: &Parameters,
+        force_fields: &[ForceField],
+        obstacles: &[Obstacle],
+    ) {
+        let cells = obstacles::rasterize(obstacles, self.grid_size_x, self.grid_size_y);
+        self.apply_forces(parameters, force_fields, &cells);
+        self.advect(parameters.time_step, &cells);
+        self.project(parameters.time_step, &cells);
+    }
+
+    fn grid_size(&self) -> Vec2 {
+        vec2(self.grid_size_x as f32, self.grid_size_y as f32)
+    }
+
+    fn cell(&self, index: usize) -> (isize, isize) {
+        (
+            (index % self.grid_size_x) as isize,
+            (index / self.grid_size_x) as isize,
+        )
+    }
+
+    fn is_outside(&self, x: isize, y: isize) -> bool {
+        x < 0 || y < 0 || x >= self.grid_size_x as isize || y >= self.grid_size_y as isize
+    }
+
+    // Same as cs_main in velocity_calculations.hlsl
+    fn apply_forces(
+        &mut self,
+        parameters: &Parameters,
+        force_fields: &[ForceField],
+        cells: &[ObstacleCellData],
+    ) {
+        let force_fields = &force_fields[..force_fields.len().min(MAX_FORCE_FIELDS)];
+        let velocity_decay = (-parameters.velocity_dissipation * parameters.time_step).exp();
+        let density_decay = (-parameters.density_dissipation * parameters.time_step).exp();
+        let grid_size = self.grid_size();
+
+        for (index, cell) in cells.iter().enumerate() {
+            if cell.solid != 0 {
+                self.velocity[index] = cell.velocity;
+                self.density[index] = 0.0;
+                continue;
+            }
+
+            let (x, y) = self.cell(index);
+            let position = (vec2(x as f32, y as f32) + 0.5) / grid_size;
+
+            let mut force = parameters.forced_velocity + parameters.gravity;
+            for field in force_fields {
+                force += evaluate_force_field(field, position);
+            }
+
+            self.velocity[index] =
+                self.velocity[index] * velocity_decay + force * parameters.time_step;
+            self.density[index] = (self.density[index] * density_decay
+                + parameters.forced_density * parameters.time_step)
+                .clamp(0.0, 1.0);
+        }
+    }
+
+    // Same as bilinear in solver.hlsl, the cells are in the order bottom left, bottom right, top
+    // left, top right
+    fn bilinear(&self, position: Vec2) -> ([usize; 4], Vec2) {
+        let clamped = position.clamp(Vec2::ZERO, self.grid_size() - 1.0);
+        let low = clamped.floor();
+        let (low_x, low_y) = (low.x as usize, low.y as usize);
+        let high_x = (low_x + 1).min(self.grid_size_x - 1);
+        let high_y = (low_y + 1).min(self.grid_size_y - 1);
+        let index = |x: usize, y: usize| x + y * self.grid_size_x;
+        (
+            [
+                index(low_x, low_y),
+                index(high_x, low_y),
+                index(low_x, high_y),
+                index(high_x, high_y),
+            ],
+            clamped - low,
+        )
+    }
+
+    // Same as cs_advect in solver.hlsl
+    fn advect(&mut self, time_step: f32, cells: &[ObstacleCellData]) {
+        let grid_size = self.grid_size();
+        let mut velocity = self.velocity.clone();
+        let mut density = self.density.clone();
+        for (index, cell) in cells.iter().enumerate() {
+            if cell.solid != 0 {
+                continue;
+            }
+
+            let (x, y) = self.cell(index);
+            let source = vec2(x as f32, y as f32) - self.velocity[index] * time_step * grid_size;
+            let ([a, b, c, d], weight) = self.bilinear(source);
+
+            velocity[index] = self.velocity[a]
+                .lerp(self.velocity[b], weight.x)
+                .lerp(self.velocity[c].lerp(self.velocity[d], weight.x), weight.y);
+            let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+            density[index] = lerp(
+                lerp(self.density[a], self.density[b], weight.x),
+                lerp(self.density[c], self.density[d], weight.x),
+                weight.y,
+            );
+        }
+        self.velocity = velocity;
+        self.density = density;
+    }
+
+    // Same as neighbour_velocity in solver.hlsl
+    fn neighbour_velocity(&self, x: isize, y: isize, center: Vec2) -> Vec2 {
+        if x < 0 || x >= self.grid_size_x as isize {
+            return vec2(-center.x, center.y);
+        }
+        if y < 0 || y >= self.grid_size_y as isize {
+            return vec2(center.x, -center.y);
+        }
+        self.velocity[x as usize + y as usize * self.grid_size_x]
+    }
+
+    // Pressure of the left, right, bottom and top neighbour, same as neighbour_pressure in
+    // solver.hlsl
+    fn neighbour_pressures(
+        &self,
+        pressure: &[f32],
+        cells: &[ObstacleCellData],
+        index: usize,
+    ) -> [f32; 4] {
+        let (x, y) = self.cell(index);
+        [(-1, 0), (1, 0), (0, -1), (0, 1)].map(|(offset_x, offset_y)| {
+            let (x, y) = (x + offset_x, y + offset_y);
+            if self.is_outside(x, y) {
+                return pressure[index];
+            }
+            let neighbour = x as usize + y as usize * self.grid_size_x;
+            if cells[neighbour].solid != 0 {
+                pressure[index]
+            } else {
+                pressure[neighbour]
+            }
+        })
+    }
+
+    // Same as cs_divergence, PRESSURE_ITERATIONS times cs_jacobi and cs_subtract_gradient in
+    // solver.hlsl
+    fn project(&mut self, time_step: f32, cells: &[ObstacleCellData]) {
+        let grid_size = self.grid_size();
+        let divergence: Vec<f32> = (0..cells.len())
+            .map(|index| {
+                if cells[index].solid != 0 {
+                    return 0.0;
+                }
+                let (x, y) = self.cell(index);
+                let center = self.velocity[index];
+                let left = self.neighbour_velocity(x - 1, y, center);
+                let right = self.neighbour_velocity(x + 1, y, center);
+                let bottom = self.neighbour_velocity(x, y - 1, center);
+                let top = self.neighbour_velocity(x, y + 1, center);
+                0.5 * ((right.x - left.x) * grid_size.x + (top.y - bottom.y) * grid_size.y)
+                    / time_step
+            })
+            .collect();
+
+        let weight = grid_size * grid_size;
+        for _ in 0..PRESSURE_ITERATIONS {
+            self.pressure = (0..cells.len())
+                .map(|index| {
+                    if cells[index].solid != 0 {
+                        return 0.0;
+                    }
+                    let [left, right, bottom, top] =
+                        self.neighbour_pressures(&self.pressure, cells, index);
+                    ((left + right) * weight.x + (bottom + top) * weight.y - divergence[index])
+                        / (2.0 * (weight.x + weight.y))
+                })
+                .collect();
+        }
+
+        for (index, cell) in cells.iter().enumerate() {
+            if cell.solid != 0 {
+                continue;
+            }
+            let [left, right, bottom, top] = self.neighbour_pressures(&self.pressure, cells, index);
+            let gradient = 0.5 * vec2(right - left, top - bottom) * grid_size;
+            self.velocity[index] -= gradient * time_step;
+        }
+    }
+
+    pub fn difference(&self, velocity: &[Vec2], density: &[f32]) -> FieldDifference {
+        FieldDifference {
+            velocity: max_or_nan(
+                self.velocity
+                    .iter()
+                    .zip(velocity)
+                    .flat_map(|(a, b)| (*a - *b).abs().to_array()),
+            ),
+            density: max_or_nan(self.density.iter().zip(density).map(|(a, b)| (a - b).abs())),
+        }
+    }
+}
+
+// Simulation that runs on the CPU only, for machines without a usable GPU. Holds the same state as
+// FluidSimulator and steps it the same way, except that dynamic obstacles are pushed by the current
+// velocity and pressure instead of a copy that lags a frame or two behind.
+pub struct CpuSimulation {
+    pub solver: CpuSolver,
+    pub parameters: Parameters,
+    pub force_fields: Vec<ForceField>,
+    pub obstacles: Vec<Obstacle>,
+    time: f32,
+    step_count: u64,
+}
+
+impl CpuSimulation {
+    // Same initial state as Scene::build. Relative paths are resolved against the directory.
+    pub fn from_scene(scene: &Scene, directory: &Path) -> io::Result<Self> {
+        scene.validate()?;
+        let [grid_size_x, grid_size_y] = scene.grid_size;
+        let (velocity, density) =
+            scene
+                .initial_fields
+                .build(grid_size_x, grid_size_y, directory)?;
+        Ok(Self {
+            solver: CpuSolver::new(grid_size_x, grid_size_y, velocity, density),
+            parameters: scene.parameters.clone(),
+            force_fields: scene.force_fields.clone(),
+            obstacles: scene.build_obstacles(),
+            time: 0.0,
+            step_count: 0,
+        })
+    }
+
+    pub fn grid_size(&self) -> (usize, usize) {
+        (self.solver.grid_size_x, self.solver.grid_size_y)
+    }
+
+    pub fn step_count(&self) -> u64 {
+        self.step_count
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    // Same as FluidSimulator::load_density_image
+    pub fn load_density_image(
+        &mut self,
+        path: impl AsRef<Path>,
+        channel: Channel,
+    ) -> io::Result<()> {
+        let (grid_size_x, grid_size_y) = self.grid_size();
+        self.solver.density =
+            image_import::read_png(path)?.resample(channel, grid_size_x, grid_size_y);
+        Ok(())
+    }
+
+    // Same as FluidSimulator::load_obstacle_mask
+    pub fn load_obstacle_mask(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let (grid_size_x, grid_size_y) = self.grid_size();
+        let mask = fluid_simulator::read_obstacle_mask(path.as_ref(), grid_size_x, grid_size_y)?;
+        self.obstacles
+            .retain(|obstacle| !matches!(obstacle.shape, Shape::Mask { .. }));
+        self.obstacles.push(mask);
+        Ok(())
+    }
+
+    // Same as FluidSimulator::run_step
+    pub fn step(&mut self) {
+        self.time += self.parameters.time_step;
+        self.step_count += 1;
+        let grid_size = self.grid_size();
+        obstacles::update_all(
+            &mut self.obstacles,
+            &self.solver.velocity,
+            &self.solver.pressure,
+            grid_size,
+            self.parameters.gravity,
+            self.time,
+            self.parameters.time_step,
+        );
+        self.solver
+            .step_with(&self.parameters, &self.force_fields, &self.obstacles);
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        fluid_simulator::build_snapshot(
+            self.grid_size(),
+            self.time,
+            self.step_count,
+            &self.parameters,
+            &self.force_fields,
+            &self.obstacles,
+            &self.solver.velocity,
+            self.solver.density.clone(),
+        )
+    }
+
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.snapshot().write(&mut writer)
+    }
+
+    // Same formats as the exports of FluidSimulator
+
+    pub fn export_vtk(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fluid_simulator::write_vtk(
+            path.as_ref(),
+            self.grid_size(),
+            &self.solver.velocity,
+            &self.solver.density,
+        )
+    }
+
+    pub fn export_npy(
+        &self,
+        velocity_path: impl AsRef<Path>,
+        density_path: impl AsRef<Path>,
+    ) -> io::Result<()> {
+        fluid_simulator::write_npy(
+            velocity_path.as_ref(),
+            density_path.as_ref(),
+            self.grid_size(),
+            &self.solver.velocity,
+            &self.solver.density,
+        )
+    }
+
+    pub fn export_npz(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fluid_simulator::write_npz(
+            path.as_ref(),
+            self.grid_size(),
+            &self.solver.velocity,
+            &self.solver.density,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec2;
+
+    use super::*;
+    use crate::obstacles::{Motion, Shape};
+
+    fn parameters() -> Parameters {
+        Parameters {
+            time_step: 0.1,
+            forced_velocity: Vec2::ZERO,
+            forced_density: 0.0,
+            velocity_dissipation: 0.0,
+            density_dissipation: 0.0,
+            gravity: Vec2::ZERO,
+        }
+    }
+
+    fn solver(grid_size_x: usize, grid_size_y: usize) -> CpuSolver {
+        let cells = grid_size_x * grid_size_y;
+        CpuSolver::new(
+            grid_size_x,
+            grid_size_y,
+            vec![Vec2::ZERO; cells],
+            vec![0.0; cells],
+        )
+    }
+
+    // Runs the force pass only, which the projection would otherwise partly undo
+    fn apply_forces(
+        solver: &mut CpuSolver,
+        parameters: &Parameters,
+        force_fields: &[ForceField],
+        obstacles: &[Obstacle],
+    ) {
+        let cells = obstacles::rasterize(obstacles, solver.grid_size_x, solver.grid_size_y);
+        solver.apply_forces(parameters, force_fields, &cells);
+    }
+
+    // Largest divergence of the fluid cells with the stencil of the projection
+    fn max_divergence(solver: &CpuSolver) -> f32 {
+        let grid_size = solver.grid_size();
+        (0..solver.velocity.len())
+            .map(|index| {
+                let (x, y) = solver.cell(index);
+                let center = solver.velocity[index];
+                let left = solver.neighbour_velocity(x - 1, y, center);
+                let right = solver.neighbour_velocity(x + 1, y, center);
+                let bottom = solver.neighbour_velocity(x, y - 1, center);
+                let top = solver.neighbour_velocity(x, y + 1, center);
+                (0.5 * ((right.x - left.x) * grid_size.x + (top.y - bottom.y) * grid_size.y)).abs()
+            })
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn integrates_uniform_forcing() {
+        let mut solver = solver(4, 3);
+        let parameters = Parameters {
+            forced_velocity: vec2(1.0, 0.0),
+            forced_density: 2.0,
+            gravity: vec2(0.0, -0.5),
+            ..parameters()
+        };
+        for _ in 0..3 {
+            apply_forces(&mut solver, &parameters, &[], &[]);
+        }
+        for velocity in &solver.velocity {
+            assert!((*velocity - vec2(0.3, -0.15)).length() < 1e-6);
+        }
+        for density in &solver.density {
+            assert!((density - 0.6).abs() < 1e-6);
+        }
+
+        // Density is clamped to [0, 1]
+        for _ in 0..3 {
+            apply_forces(&mut solver, &parameters, &[], &[]);
+        }
+        assert!(solver.density.iter().all(|&density| density == 1.0));
+    }
+
+    #[test]
+    fn dissipates_exponentially() {
+        let mut solver = CpuSolver::new(1, 1, vec![vec2(2.0, -1.0)], vec![0.5]);
+        let parameters = Parameters {
+            velocity_dissipation: 0.5,
+            density_dissipation: 2.0,
+            ..parameters()
+        };
+        solver.step_with(&parameters, &[], &[]);
+        assert!((solver.velocity[0] - vec2(2.0, -1.0) * (-0.05f32).exp()).length() < 1e-6);
+        assert!((solver.density[0] - 0.5 * (-0.2f32).exp()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn applies_wind_inside_its_box_only() {
+        let mut solver = solver(4, 1);
+        let wind = ForceField::Wind {
+            min: vec2(0.0, 0.0),
+            max: vec2(0.5, 1.0),
+            force: vec2(0.0, 10.0),
+        };
+        solver.step_with(&parameters(), &[wind], &[]);
+        // Cell centers are at x = 0.125, 0.375, 0.625 and 0.875
+        assert_eq!(
+            solver.velocity,
+            [vec2(0.0, 1.0), vec2(0.0, 1.0), Vec2::ZERO, Vec2::ZERO]
+        );
+    }
+
+    #[test]
+    fn radial_force_points_to_the_center() {
+        let mut solver = solver(3, 3);
+        let radial = ForceField::Radial {
+            center: vec2(0.5, 0.5),
+            radius: 1.0,
+            strength: 1.0,
+        };
+        apply_forces(&mut solver, &parameters(), &[radial], &[]);
+        // The center cell is at distance zero and gets no force
+        assert_eq!(solver.velocity[4], Vec2::ZERO);
+        let left = solver.velocity[3];
+        assert!(left.x > 0.0 && left.y.abs() < 1e-6);
+        let top = solver.velocity[7];
+        assert!(top.y < 0.0 && top.x.abs() < 1e-6);
+    }
+
+    #[test]
+    fn obstacles_override_the_fluid() {
+        let mut solver = CpuSolver::new(3, 3, vec![vec2(1.0, 1.0); 9], vec![1.0; 9]);
+        let obstacles = [Obstacle::new(
+            Shape::Circle { radius: 0.1 },
+            vec2(0.5, 0.5),
+            Motion::Static,
+        )];
+        apply_forces(&mut solver, &parameters(), &[], &obstacles);
+        assert_eq!(solver.velocity[4], Vec2::ZERO);
+        assert_eq!(solver.velocity[0], vec2(1.0, 1.0));
+
+        // Advection and the projection leave the solid cells alone
+        solver.step_with(&parameters(), &[], &obstacles);
+        assert_eq!(solver.velocity[4], Vec2::ZERO);
+        assert_eq!(solver.density[4], 0.0);
+        assert_eq!(solver.pressure[4], 0.0);
+        assert_eq!(solver.density[0], 1.0);
+    }
+
+    #[test]
+    fn advects_along_the_velocity() {
+        // One cell per step to the right
+        let mut solver = CpuSolver::new(4, 1, vec![vec2(2.0, 0.0); 4], vec![1.0, 0.0, 0.0, 0.0]);
+        let cells = obstacles::rasterize(&[], 4, 1);
+        solver.advect(0.125, &cells);
+        assert_eq!(solver.density, [1.0, 1.0, 0.0, 0.0]);
+        solver.advect(0.125, &cells);
+        assert_eq!(solver.density, [1.0, 1.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn projection_reduces_divergence() {
+        let mut solver = solver(16, 16);
+        for (index, velocity) in solver.velocity.iter_mut().enumerate() {
+            let position = (vec2((index % 16) as f32, (index / 16) as f32) + 0.5) / 16.0;
+            *velocity = position - 0.5;
+        }
+        let cells = obstacles::rasterize(&[], 16, 16);
+        let initial = max_divergence(&solver);
+        // The pressure carries over between steps, so the residual keeps shrinking
+        for _ in 0..10 {
+            solver.project(0.1, &cells);
+        }
+        assert!(max_divergence(&solver) < 0.1 * initial);
+    }
+
+    #[test]
+    fn difference_reports_nan() {
+        let solver = CpuSolver::new(2, 1, vec![Vec2::ZERO; 2], vec![0.0; 2]);
+        let difference = solver.difference(&[vec2(0.5, -2.0), Vec2::ZERO], &[0.25, 0.0]);
+        assert_eq!(
+            difference,
+            FieldDifference {
+                velocity: 2.0,
+                density: 0.25,
+            }
+        );
+        assert!(difference.within(2.0) && !difference.within(1.0));
+
+        let difference = solver.difference(&[Vec2::ZERO, Vec2::NAN], &[0.0, 0.0]);
+        assert!(difference.velocity.is_nan() && !difference.within(f32::INFINITY));
+    }
+}
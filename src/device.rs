@@ -0,0 +1,59 @@
+use std::{fmt, sync::Arc};
+
+// Push constant space used by the compute and render pipelines
+const MAX_PUSH_CONSTANT_SIZE: u32 = 128;
+
+#[derive(Debug)]
+pub enum DeviceError {
+    NoAdapter,
+    RequestDevice(wgpu::RequestDeviceError),
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceError::NoAdapter => write!(f, "No matching adapter found"),
+            DeviceError::RequestDevice(error) => write!(f, "Failed to create device: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for DeviceError {}
+
+// Creates a device with the features the simulator needs, without a surface. The adapter is the
+// first one whose name contains adapter_name, or the high performance one if no name is given.
+pub fn create_headless_device(
+    backends: wgpu::Backends,
+    adapter_name: Option<&str>,
+) -> Result<(Arc<wgpu::Device>, Arc<wgpu::Queue>, wgpu::AdapterInfo), DeviceError> {
+    let instance = wgpu::Instance::new(backends);
+    let adapter = match adapter_name {
+        Some(name) => instance
+            .enumerate_adapters(backends)
+            .find(|adapter| adapter.get_info().name.contains(name)),
+        None => {
+            futures::executor::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                force_fallback_adapter: false,
+                compatible_surface: None,
+            }))
+        }
+    }
+    .ok_or(DeviceError::NoAdapter)?;
+
+    let (device, queue) = futures::executor::block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            label: Some("headless_device"),
+            // Timestamp queries are optional, the GPU profiler is disabled without them
+            features: wgpu::Features::PUSH_CONSTANTS
+                | (adapter.features() & wgpu::Features::TIMESTAMP_QUERY),
+            limits: wgpu::Limits {
+                max_push_constant_size: MAX_PUSH_CONSTANT_SIZE,
+                ..wgpu::Limits::default()
+            },
+        },
+        None,
+    ))
+    .map_err(DeviceError::RequestDevice)?;
+    Ok((Arc::new(device), Arc::new(queue), adapter.get_info()))
+}
@@ -0,0 +1,300 @@
+use std::collections::VecDeque;
+
+use wgpu::{PushConstantRange, ShaderStages};
+
+use crate::readback::Readback;
+
+// Number of samples kept for the plots
+pub const HISTORY_LENGTH: usize = 600;
+// Reductions that can be in flight at the same time. Steps are skipped while all are busy.
+const READBACK_COUNT: usize = 4;
+// Matches GROUP_WIDTH in diagnostics.hlsl. cs_reduce_cells reduces square groups of cells.
+const GROUP_WIDTH: usize = 16;
+
+// Integral quantities of one step. Solid cells are left out.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Sample {
+    pub step: u64,
+    pub time: f32,
+    pub max_divergence: f32,
+    pub mean_divergence: f32,
+    pub kinetic_energy: f32,
+    pub enstrophy: f32,
+    pub mass: f32,
+    pub max_speed: f32,
+    // Cells with a NaN velocity, divergence or density. They are left out of everything else.
+    pub nan_cells: u32,
+}
+
+impl Sample {
+    // Name and value of every quantity, in display order
+    pub fn quantities(&self) -> [(&'static str, f32); 6] {
+        [
+            ("max divergence", self.max_divergence),
+            ("mean divergence", self.mean_divergence),
+            ("kinetic energy", self.kinetic_energy),
+            ("enstrophy", self.enstrophy),
+            ("mass", self.mass),
+            ("max speed", self.max_speed),
+        ]
+    }
+}
+
+// Matches the ReductionData struct in diagnostics.hlsl
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct ReductionData {
+    divergence_sum: f32,
+    divergence_max: f32,
+    kinetic_energy: f32,
+    enstrophy: f32,
+    mass: f32,
+    speed_max: f32,
+    fluid_cells: u32,
+    nan_cells: u32,
+}
+
+unsafe impl bytemuck::Pod for ReductionData {}
+unsafe impl bytemuck::Zeroable for ReductionData {}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct PushConstants {
+    grid_size_x: u32,
+    grid_size_y: u32,
+    partial_count: u32,
+    group_count_x: u32,
+}
+
+unsafe impl bytemuck::Pod for PushConstants {}
+unsafe impl bytemuck::Zeroable for PushConstants {}
+
+// GPU reductions over the simulation fields. The results are copied into a small ring of buffers
+// and mapped without waiting, so they arrive a frame or two after the step that produced them.
+pub(crate) struct Diagnostics {
+    reduce_cells_pipeline: wgpu::ComputePipeline,
+    reduce_partials_pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+    _partials_buffer: wgpu::Buffer,
+    result_buffer: wgpu::Buffer,
+    grid_size_x: usize,
+    grid_size_y: usize,
+    // Groups of cs_reduce_cells in x and y, one partial result each
+    group_count: (usize, usize),
+    // Step and time of the fields each readback holds the reductions of
+    readbacks: Vec<Readback<(u64, f32)>>,
+    history: VecDeque<Sample>,
+}
+
+fn storage_entry(binding: u32, read_only: bool) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            has_dynamic_offset: false,
+            min_binding_size: None,
+            ty: wgpu::BufferBindingType::Storage { read_only },
+        },
+        count: None,
+    }
+}
+
+fn buffer_entry(binding: u32, buffer: &wgpu::Buffer) -> wgpu::BindGroupEntry<'_> {
+    wgpu::BindGroupEntry {
+        binding,
+        resource: buffer.as_entire_binding(),
+    }
+}
+
+impl Diagnostics {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        reduce_cells_module: &wgpu::ShaderModule,
+        reduce_partials_module: &wgpu::ShaderModule,
+        velocity_buffer: &wgpu::Buffer,
+        density_buffer: &wgpu::Buffer,
+        obstacles_buffer: &wgpu::Buffer,
+        grid_size_x: usize,
+        grid_size_y: usize,
+    ) -> Self {
+        let group_count = (
+            grid_size_x.div_ceil(GROUP_WIDTH),
+            grid_size_y.div_ceil(GROUP_WIDTH),
+        );
+        let partial_count = group_count.0 * group_count.1;
+        let reduction_size = std::mem::size_of::<ReductionData>() as u64;
+
+        let partials_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("diagnostics_partials_buffer"),
+            usage: wgpu::BufferUsages::STORAGE,
+            size: partial_count as u64 * reduction_size,
+            mapped_at_creation: false,
+        });
+        let result_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("diagnostics_result_buffer"),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            size: reduction_size,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("diagnostics_bind_group_layout"),
+            entries: &[
+                storage_entry(0, true),
+                storage_entry(1, true),
+                storage_entry(2, true),
+                storage_entry(3, false),
+                storage_entry(4, false),
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("diagnostics_bind_group"),
+            layout: &bind_group_layout,
+            entries: &[
+                buffer_entry(0, velocity_buffer),
+                buffer_entry(1, density_buffer),
+                buffer_entry(2, obstacles_buffer),
+                buffer_entry(3, &partials_buffer),
+                buffer_entry(4, &result_buffer),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("diagnostics_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[PushConstantRange {
+                stages: ShaderStages::COMPUTE,
+                range: 0..std::mem::size_of::<PushConstants>() as u32,
+            }],
+        });
+        let reduce_cells_pipeline =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("diagnostics_reduce_cells_pipeline"),
+                layout: Some(&pipeline_layout),
+                module: reduce_cells_module,
+                entry_point: "cs_reduce_cells",
+            });
+        let reduce_partials_pipeline =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("diagnostics_reduce_partials_pipeline"),
+                layout: Some(&pipeline_layout),
+                module: reduce_partials_module,
+                entry_point: "cs_reduce_partials",
+            });
+
+        let readbacks = (0..READBACK_COUNT)
+            .map(|_| Readback::new(device, "diagnostics_readback_buffer", reduction_size))
+            .collect();
+
+        Self {
+            reduce_cells_pipeline,
+            reduce_partials_pipeline,
+            bind_group,
+            _partials_buffer: partials_buffer,
+            result_buffer,
+            grid_size_x,
+            grid_size_y,
+            group_count,
+            readbacks,
+            history: VecDeque::with_capacity(HISTORY_LENGTH),
+        }
+    }
+
+    // Records the reductions of the current fields and the copy of the result into a free readback
+    // buffer. Does nothing if all of them are still waiting to be read.
+    pub(crate) fn record(&self, encoder: &mut wgpu::CommandEncoder, step: u64, time: f32) {
+        let readback = match self.readbacks.iter().find(|readback| readback.is_free()) {
+            Some(readback) => readback,
+            None => return,
+        };
+
+        let mut c_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("diagnostics_compute_pass"),
+        });
+        let push_constants = PushConstants {
+            grid_size_x: self.grid_size_x as u32,
+            grid_size_y: self.grid_size_y as u32,
+            partial_count: (self.group_count.0 * self.group_count.1) as u32,
+            group_count_x: self.group_count.0 as u32,
+        };
+        c_pass.push_debug_group("diagnostics_reduce");
+        for (pipeline, (group_count_x, group_count_y)) in [
+            (&self.reduce_cells_pipeline, self.group_count),
+            (&self.reduce_partials_pipeline, (1, 1)),
+        ] {
+            c_pass.set_pipeline(pipeline);
+            c_pass.set_bind_group(0, &self.bind_group, &[]);
+            c_pass.set_push_constants(0, bytemuck::cast_slice(&[push_constants]));
+            c_pass.dispatch(group_count_x as u32, group_count_y as u32, 1);
+        }
+        c_pass.pop_debug_group();
+        drop(c_pass);
+
+        readback.try_record(|buffer| {
+            encoder.copy_buffer_to_buffer(
+                &self.result_buffer,
+                0,
+                buffer,
+                0,
+                std::mem::size_of::<ReductionData>() as u64,
+            );
+            (step, time)
+        });
+    }
+
+    // Starts mapping the buffers recorded since the last call and collects the ones that are ready.
+    // Must be called after the command buffers from record have been submitted.
+    pub(crate) fn poll(&mut self, device: &wgpu::Device) {
+        device.poll(wgpu::Maintain::Poll);
+
+        let mut samples: Vec<_> = self
+            .readbacks
+            .iter()
+            .filter_map(|readback| {
+                readback.poll(|(step, time), data| {
+                    sample(bytemuck::from_bytes::<ReductionData>(data), step, time)
+                })
+            })
+            .collect();
+
+        samples.sort_by_key(|sample| sample.step);
+        for sample in samples {
+            // The simulation was reset or restarted
+            if self
+                .history
+                .back()
+                .is_some_and(|last| sample.step <= last.step)
+            {
+                self.history.clear();
+            }
+            if self.history.len() == HISTORY_LENGTH {
+                self.history.pop_front();
+            }
+            self.history.push_back(sample);
+        }
+    }
+
+    // Oldest sample first
+    pub(crate) fn history(&self) -> &VecDeque<Sample> {
+        &self.history
+    }
+}
+
+fn sample(data: &ReductionData, step: u64, time: f32) -> Sample {
+    Sample {
+        step,
+        time,
+        max_divergence: data.divergence_max,
+        mean_divergence: if data.fluid_cells > data.nan_cells {
+            data.divergence_sum / (data.fluid_cells - data.nan_cells) as f32
+        } else {
+            0.0
+        },
+        kinetic_energy: data.kinetic_energy,
+        enstrophy: data.enstrophy,
+        mass: data.mass,
+        max_speed: data.speed_max,
+        nan_cells: data.nan_cells,
+    }
+}
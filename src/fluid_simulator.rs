@@ -1,10 +1,86 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    path::Path,
+    sync::Arc,
+};
+
 use glam::{vec2, Vec2};
+use serde::{Deserialize, Serialize};
 use wgpu::{util::DeviceExt, ComputePipelineDescriptor, PushConstantRange, ShaderStages};
 
-const GRID_SIZE_X: usize = 20;
-const GRID_SIZE_Y: usize = 20;
-const VELOCITY_BUFFER_SIZE: usize = GRID_SIZE_X * GRID_SIZE_Y * std::mem::size_of::<glam::Vec2>();
+use crate::{
+    diagnostics::{self, Diagnostics},
+    image_import::{self, Channel},
+    numpy_io,
+    obstacles::{self, Motion, Obstacle, ObstacleCellData, Shape},
+    probe::{Probe, ProbeValue},
+    profiler::{FrameTimings, GpuProfiler},
+    readback::Readback,
+    scene::Parameters,
+    shaders::spirv,
+    snapshot::Snapshot,
+    solver::Solver,
+    vtk_export,
+};
+
+pub const DEFAULT_GRID_SIZE_X: usize = 20;
+pub const DEFAULT_GRID_SIZE_Y: usize = 20;
+// Largest grid size per axis. The obstacle cells are the largest per-cell buffer at 16 bytes, which
+// is 16 MB at this size and well within the default 128 MB storage buffer binding limit.
+pub const MAX_GRID_SIZE: usize = 1024;
+pub const MAX_FORCE_FIELDS: usize = 16;
+
+// Procedural forces evaluated in the force pass. Positions are in grid space, from (0, 0) to (1, 1)
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ForceField {
+    // Pulls towards the center for positive strength and pushes away for negative
+    Radial {
+        center: Vec2,
+        radius: f32,
+        strength: f32,
+    },
+    // Spins counter-clockwise around the center for positive strength
+    Vortex {
+        center: Vec2,
+        radius: f32,
+        strength: f32,
+    },
+    // Constant force inside the rectangle
+    Wind {
+        min: Vec2,
+        max: Vec2,
+        force: Vec2,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Visualization {
+    Density,
+    Velocity,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunState {
+    Running,
+    Paused,
+    // Runs for the given number of steps, then pauses
+    Stepping(u32),
+}
+
+// Fields and obstacles restored by FluidSimulator::reset
+struct InitialConditions {
+    velocity: Vec<Vec2>,
+    density: Vec<f32>,
+    obstacles: Vec<Obstacle>,
+}
+
 pub struct FluidSimulator {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    surface_format: wgpu::TextureFormat,
     render_pipeline: wgpu::RenderPipeline,
     density_render_pipeline: wgpu::RenderPipeline,
     compute_pipeline: wgpu::ComputePipeline,
@@ -12,11 +88,39 @@ pub struct FluidSimulator {
     compute_uniform_bind_group: wgpu::BindGroup,
     vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
-    _velocity_buffer: wgpu::Buffer,
+    velocity_buffer: wgpu::Buffer,
+    density_buffer: wgpu::Buffer,
     _constants_buffer: wgpu::Buffer,
+    force_fields_buffer: wgpu::Buffer,
+    obstacles_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    // Velocity and pressure the forces on the dynamic obstacles are computed from. They are read
+    // back without waiting, so they lag a frame or two behind the fields, and are empty until the
+    // first read arrives. The readback is tagged with the initial conditions it was recorded under.
+    coupling_readback: Readback<u64>,
+    coupling_velocity: Vec<Vec2>,
+    coupling_pressure: Vec<f32>,
+    initial_conditions_count: u64,
+    solver: Solver,
+    diagnostics: Diagnostics,
+    profiler: Option<GpuProfiler>,
+    probe: Probe,
+    grid_size_x: usize,
+    grid_size_y: usize,
+    time: f32,
+    step_count: u64,
+    initial_conditions: InitialConditions,
 
     pub forced_velocity: Vec2,
     pub forced_density: f32,
+    pub time_step: f32,
+    // Exponential decay rates per second, applied before the forces are added
+    pub velocity_dissipation: f32,
+    pub density_dissipation: f32,
+    pub gravity: Vec2,
+    pub force_fields: Vec<ForceField>,
+    pub obstacles: Vec<Obstacle>,
+    pub run_state: RunState,
 }
 
 #[derive(Clone, Copy)]
@@ -34,69 +138,243 @@ unsafe impl bytemuck::Zeroable for ConstantsData {}
 struct PushConstants {
     forced_velocity: Vec2,
     forced_density: f32,
+    time_step: f32,
+    velocity_dissipation: f32,
+    density_dissipation: f32,
+    gravity: Vec2,
+    force_field_count: u32,
 }
 
 unsafe impl bytemuck::Pod for PushConstants {}
 unsafe impl bytemuck::Zeroable for PushConstants {}
 
-impl FluidSimulator {
-    fn compile_shader(
-        compiler: &hassle_rs::DxcCompiler,
-        library: &hassle_rs::DxcLibrary,
-        blob: &hassle_rs::DxcBlobEncoding,
-        entry_point: &str,
-        profile: &str,
-    ) -> Vec<u8> {
-        let result = match compiler.compile(
-            &blob,
-            "Shader",
-            entry_point,
-            profile,
-            &["-spirv"],
-            None,
-            &[],
-        ) {
-            Ok(result) => result,
-            Err(result) => {
-                let error_blob = result
-                    .0
-                    .get_error_buffer()
-                    .map_err(hassle_rs::utils::HassleError::Win32Error)
-                    .unwrap();
-                println!("{}", library.get_blob_as_string(&error_blob));
-                panic!();
+const FORCE_FIELD_KIND_RADIAL: u32 = 0;
+const FORCE_FIELD_KIND_VORTEX: u32 = 1;
+const FORCE_FIELD_KIND_WIND: u32 = 2;
+
+// Matches the ForceFieldData struct in velocity_calculations.hlsl
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct ForceFieldData {
+    kind: u32,
+    radius: f32,
+    strength: f32,
+    _padding: f32,
+    // Center for radial and vortex fields, min corner for wind
+    position: Vec2,
+    // Max corner for wind, unused otherwise
+    extent: Vec2,
+    // Force for wind, unused otherwise
+    force: Vec2,
+}
+
+unsafe impl bytemuck::Pod for ForceFieldData {}
+unsafe impl bytemuck::Zeroable for ForceFieldData {}
+
+impl From<&ForceField> for ForceFieldData {
+    fn from(field: &ForceField) -> Self {
+        let (kind, position, radius, strength, extent, force) = match *field {
+            ForceField::Radial {
+                center,
+                radius,
+                strength,
+            } => (
+                FORCE_FIELD_KIND_RADIAL,
+                center,
+                radius,
+                strength,
+                Vec2::ZERO,
+                Vec2::ZERO,
+            ),
+            ForceField::Vortex {
+                center,
+                radius,
+                strength,
+            } => (
+                FORCE_FIELD_KIND_VORTEX,
+                center,
+                radius,
+                strength,
+                Vec2::ZERO,
+                Vec2::ZERO,
+            ),
+            ForceField::Wind { min, max, force } => {
+                (FORCE_FIELD_KIND_WIND, min, 0.0, 0.0, max, force)
             }
         };
-        result.get_result().unwrap().to_vec()
-    }
-
-    pub fn new(renderer: &rend3::Renderer, surface_format: wgpu::TextureFormat) -> Self {
-        let dxc = hassle_rs::Dxc::new().unwrap();
-        let compiler = dxc.create_compiler().unwrap();
-        let library = dxc.create_library().unwrap();
-        let blob = library
-            .create_blob_with_encoding_from_str(include_str!("shaders/velocity_field.hlsl"))
-            .unwrap();
-        let vs_code =
-            FluidSimulator::compile_shader(&compiler, &library, &blob, "vs_main", "vs_6_6");
-        let ps_code =
-            FluidSimulator::compile_shader(&compiler, &library, &blob, "ps_main", "ps_6_6");
-
-        let blob = library
-            .create_blob_with_encoding_from_str(include_str!("shaders/velocity_calculations.hlsl"))
-            .unwrap();
-        let cs_code =
-            FluidSimulator::compile_shader(&compiler, &library, &blob, "cs_main", "cs_6_6");
-
-        let blob = library
-            .create_blob_with_encoding_from_str(include_str!("shaders/density_visualize.hlsl"))
-            .unwrap();
-        let vs_density_code =
-            FluidSimulator::compile_shader(&compiler, &library, &blob, "vs_main", "vs_6_6");
-        let ps_density_code =
-            FluidSimulator::compile_shader(&compiler, &library, &blob, "ps_main", "ps_6_6");
-
-        let device: &wgpu::Device = &renderer.device;
+        Self {
+            kind,
+            radius,
+            strength,
+            _padding: 0.0,
+            position,
+            extent,
+            force,
+        }
+    }
+}
+
+// Reads a .npy array, failing if it does not have the expected shape. Errors name the file.
+pub(crate) fn read_npy_field(path: &Path, expected_shape: &[usize]) -> io::Result<Vec<f32>> {
+    numpy_io::read_npy(&mut BufReader::new(File::open(path)?), expected_shape)
+        .map_err(|error| io::Error::new(error.kind(), format!("{}: {}", path.display(), error)))
+}
+
+// Writers of the export formats, shared by the GPU simulator and the CPU simulation
+
+pub(crate) fn write_vtk(
+    path: &Path,
+    grid_size: (usize, usize),
+    velocity: &[Vec2],
+    density: &[f32],
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    vtk_export::write_image_data(&mut writer, grid_size.0, grid_size.1, velocity, density)
+}
+
+pub(crate) fn write_npy(
+    velocity_path: &Path,
+    density_path: &Path,
+    grid_size: (usize, usize),
+    velocity: &[Vec2],
+    density: &[f32],
+) -> io::Result<()> {
+    let (grid_size_x, grid_size_y) = grid_size;
+    numpy_io::write_npy(
+        &mut BufWriter::new(File::create(velocity_path)?),
+        &[grid_size_y, grid_size_x, 2],
+        &velocity
+            .iter()
+            .flat_map(|velocity| [velocity.x, velocity.y])
+            .collect::<Vec<_>>(),
+    )?;
+    numpy_io::write_npy(
+        &mut BufWriter::new(File::create(density_path)?),
+        &[grid_size_y, grid_size_x],
+        density,
+    )
+}
+
+pub(crate) fn write_npz(
+    path: &Path,
+    grid_size: (usize, usize),
+    velocity: &[Vec2],
+    density: &[f32],
+) -> io::Result<()> {
+    let (grid_size_x, grid_size_y) = grid_size;
+    let velocity = velocity
+        .iter()
+        .flat_map(|velocity| [velocity.x, velocity.y])
+        .collect::<Vec<_>>();
+    numpy_io::write_npz(
+        BufWriter::new(File::create(path)?),
+        &[
+            ("velocity", &[grid_size_y, grid_size_x, 2], &velocity),
+            ("density", &[grid_size_y, grid_size_x], density),
+        ],
+    )
+}
+
+// Parameters are stored by the names FluidSimulator::restore_snapshot looks up
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn build_snapshot(
+    grid_size: (usize, usize),
+    time: f32,
+    step_count: u64,
+    parameters: &Parameters,
+    force_fields: &[ForceField],
+    obstacles: &[Obstacle],
+    velocity: &[Vec2],
+    density: Vec<f32>,
+) -> Snapshot {
+    Snapshot {
+        grid_size_x: grid_size.0,
+        grid_size_y: grid_size.1,
+        step_count,
+        parameters: [
+            ("time", time),
+            ("time_step", parameters.time_step),
+            ("forced_velocity.x", parameters.forced_velocity.x),
+            ("forced_velocity.y", parameters.forced_velocity.y),
+            ("forced_density", parameters.forced_density),
+            ("velocity_dissipation", parameters.velocity_dissipation),
+            ("density_dissipation", parameters.density_dissipation),
+            ("gravity.x", parameters.gravity.x),
+            ("gravity.y", parameters.gravity.y),
+        ]
+        .iter()
+        .map(|(name, value)| (name.to_string(), *value))
+        .collect(),
+        force_fields: force_fields.to_vec(),
+        obstacles: obstacles.to_vec(),
+        fields: vec![
+            (
+                "velocity".to_string(),
+                2,
+                velocity
+                    .iter()
+                    .flat_map(|velocity| [velocity.x, velocity.y])
+                    .collect(),
+            ),
+            ("density".to_string(), 1, density),
+        ],
+    }
+}
+
+// Static obstacle covering the whole domain, solid wherever the image is bright and opaque
+pub(crate) fn read_obstacle_mask(
+    path: &Path,
+    grid_size_x: usize,
+    grid_size_y: usize,
+) -> io::Result<Obstacle> {
+    let image = image_import::read_png(path)?;
+    let luminance = image.resample(Channel::Luminance, grid_size_x, grid_size_y);
+    let alpha = image.resample(Channel::Alpha, grid_size_x, grid_size_y);
+    let solid = luminance
+        .iter()
+        .zip(&alpha)
+        .map(|(luminance, alpha)| luminance * alpha >= 0.5)
+        .collect();
+    Ok(Obstacle::new(
+        Shape::Mask {
+            half_extents: Vec2::splat(0.5),
+            width: grid_size_x,
+            height: grid_size_y,
+            solid,
+        },
+        Vec2::splat(0.5),
+        Motion::Static,
+    ))
+}
+
+impl FluidSimulator {
+    // Creates the simulator on a device, which needs push constants. Timestamp queries are used for
+    // the profiler when the device has them. The surface format is the format the visualizations
+    // render to. Panics if the grid size is outside 1 to MAX_GRID_SIZE per axis.
+    pub fn with_device(
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        surface_format: wgpu::TextureFormat,
+        grid_size_x: usize,
+        grid_size_y: usize,
+    ) -> Self {
+        assert!(
+            (1..=MAX_GRID_SIZE).contains(&grid_size_x)
+                && (1..=MAX_GRID_SIZE).contains(&grid_size_y),
+            "grid size {}x{} is outside 1x1 to {}x{}",
+            grid_size_x,
+            grid_size_y,
+            MAX_GRID_SIZE,
+            MAX_GRID_SIZE
+        );
+        let vs_code = spirv!("velocity_field", "vs_main", "vs_6_6");
+        let ps_code = spirv!("velocity_field", "ps_main", "ps_6_6");
+        let cs_code = spirv!("velocity_calculations", "cs_main", "cs_6_6");
+        let cs_reduce_cells_code = spirv!("diagnostics", "cs_reduce_cells", "cs_6_6");
+        let cs_reduce_partials_code = spirv!("diagnostics", "cs_reduce_partials", "cs_6_6");
+        let vs_density_code = spirv!("density_visualize", "vs_main", "vs_6_6");
+        let ps_density_code = spirv!("density_visualize", "ps_main", "ps_6_6");
+
         let vs_shader = wgpu::ShaderModuleDescriptor {
             label: Some("velocity_field_vs_shader"),
             source: wgpu::util::make_spirv(vs_code.as_slice()),
@@ -115,6 +393,18 @@ impl FluidSimulator {
         };
         let cs_module = device.create_shader_module(&cs_shader);
 
+        let reduce_cells_shader = wgpu::ShaderModuleDescriptor {
+            label: Some("diagnostics_reduce_cells_cs_shader"),
+            source: wgpu::util::make_spirv(cs_reduce_cells_code.as_slice()),
+        };
+        let reduce_cells_module = device.create_shader_module(&reduce_cells_shader);
+
+        let reduce_partials_shader = wgpu::ShaderModuleDescriptor {
+            label: Some("diagnostics_reduce_partials_cs_shader"),
+            source: wgpu::util::make_spirv(cs_reduce_partials_code.as_slice()),
+        };
+        let reduce_partials_module = device.create_shader_module(&reduce_partials_shader);
+
         let density_vs_shader = wgpu::ShaderModuleDescriptor {
             label: Some("density_field_vs_shader"),
             source: wgpu::util::make_spirv(vs_density_code.as_slice()),
@@ -127,17 +417,24 @@ impl FluidSimulator {
         };
         let density_ps_module = device.create_shader_module(&density_ps_shader);
 
+        let cell_count = grid_size_x * grid_size_y;
+        let velocity_buffer_size = (cell_count * std::mem::size_of::<Vec2>()) as u64;
+
         let velocity_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("velocity_field_velocity_buffer"),
-            usage: wgpu::BufferUsages::STORAGE,
-            size: VELOCITY_BUFFER_SIZE as u64,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            size: velocity_buffer_size,
             mapped_at_creation: false,
         });
 
         let density_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("density_field_buffer"),
-            usage: wgpu::BufferUsages::STORAGE,
-            size: (GRID_SIZE_X * GRID_SIZE_Y * std::mem::size_of::<f32>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            size: (cell_count * std::mem::size_of::<f32>()) as u64,
             mapped_at_creation: false,
         });
 
@@ -145,11 +442,61 @@ impl FluidSimulator {
             label: Some("velocity_field_constants_data_buffer"),
             usage: wgpu::BufferUsages::UNIFORM,
             contents: bytemuck::cast_slice(&[ConstantsData {
-                grid_size_x: GRID_SIZE_X as u32,
-                grid_size_y: GRID_SIZE_Y as u32,
+                grid_size_x: grid_size_x as u32,
+                grid_size_y: grid_size_y as u32,
             }]),
         });
 
+        let force_fields_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("force_fields_buffer"),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            size: (MAX_FORCE_FIELDS * std::mem::size_of::<ForceFieldData>()) as u64,
+            mapped_at_creation: false,
+        });
+
+        let obstacles_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("obstacles_buffer"),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            size: (cell_count * std::mem::size_of::<ObstacleCellData>()) as u64,
+            mapped_at_creation: false,
+        });
+
+        // Large enough for the biggest field
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("fluid_fields_readback_buffer"),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            size: velocity_buffer_size,
+            mapped_at_creation: false,
+        });
+
+        // The velocity followed by the pressure
+        let coupling_readback = Readback::new(
+            &device,
+            "coupling_readback_buffer",
+            velocity_buffer_size + (cell_count * std::mem::size_of::<f32>()) as u64,
+        );
+
+        let solver = Solver::new(
+            &device,
+            &constants_buffer,
+            &velocity_buffer,
+            &density_buffer,
+            &obstacles_buffer,
+            grid_size_x,
+            grid_size_y,
+        );
+
+        let diagnostics = Diagnostics::new(
+            &device,
+            &reduce_cells_module,
+            &reduce_partials_module,
+            &velocity_buffer,
+            &density_buffer,
+            &obstacles_buffer,
+            grid_size_x,
+            grid_size_y,
+        );
+
         let uniform_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("velocity_field_bind_group_layout"),
@@ -184,6 +531,16 @@ impl FluidSimulator {
                         },
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        },
+                        count: None,
+                    },
                 ],
             });
 
@@ -215,6 +572,14 @@ impl FluidSimulator {
                         size: None,
                     }),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &obstacles_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
             ],
         });
 
@@ -252,6 +617,26 @@ impl FluidSimulator {
                         },
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        },
+                        count: None,
+                    },
                 ],
             });
 
@@ -283,6 +668,22 @@ impl FluidSimulator {
                         size: None,
                     }),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &force_fields_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &obstacles_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
             ],
         });
 
@@ -389,7 +790,13 @@ impl FluidSimulator {
             usage: wgpu::BufferUsages::INDEX,
         });
 
+        let profiler = GpuProfiler::new(&device, &queue);
+        let probe = Probe::new(&device);
+
         Self {
+            device,
+            queue,
+            surface_format,
             render_pipeline,
             density_render_pipeline,
             compute_pipeline,
@@ -397,115 +804,829 @@ impl FluidSimulator {
             compute_uniform_bind_group,
             vertex_buffer,
             index_buffer,
-            _velocity_buffer: velocity_buffer,
+            velocity_buffer,
+            density_buffer,
             _constants_buffer: constants_buffer,
+            force_fields_buffer,
+            obstacles_buffer,
+            readback_buffer,
+            coupling_readback,
+            coupling_velocity: Vec::new(),
+            coupling_pressure: Vec::new(),
+            initial_conditions_count: 0,
+            solver,
+            diagnostics,
+            profiler,
+            probe,
+            grid_size_x,
+            grid_size_y,
+            time: 0.0,
+            step_count: 0,
+            initial_conditions: InitialConditions {
+                velocity: vec![Vec2::ZERO; cell_count],
+                density: vec![0.0; cell_count],
+                obstacles: Vec::new(),
+            },
             forced_velocity: vec2(0.0, 0.0),
             forced_density: 0.0,
+            time_step: 1.0 / 60.0,
+            velocity_dissipation: 1.0,
+            density_dissipation: 1.0,
+            gravity: Vec2::ZERO,
+            force_fields: Vec::new(),
+            obstacles: Vec::new(),
+            run_state: RunState::Running,
         }
     }
 
-    pub fn add_forces_in_field_to_graph<'node>(&'node self, graph: &mut rend3::RenderGraph<'node>) {
-        let mut builder = graph.add_node("fluid_simulator_add_forces_and_density");
+    // Number of cells in x and y
+    pub fn grid_size(&self) -> (usize, usize) {
+        (self.grid_size_x, self.grid_size_y)
+    }
 
-        let _data_output = builder.add_data_output::<_, wgpu::Buffer>("Fluid Fields");
+    fn cell_count(&self) -> usize {
+        self.grid_size_x * self.grid_size_y
+    }
 
-        builder.build(
-            move |_pt, _renderer, encoder_or_pass, _temps, _ready, _graph_data| {
-                let encoder = encoder_or_pass.get_encoder();
+    // Overwrites the simulation fields. Both slices are row major with one element per cell,
+    // starting from the bottom left cell.
+    pub fn write_fields(&self, velocity: &[Vec2], density: &[f32]) {
+        assert_eq!(velocity.len(), self.cell_count());
+        assert_eq!(density.len(), self.cell_count());
 
-                let mut c_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                    label: Some("velocity_calculation_compute_pass"),
-                });
+        self.queue.write_buffer(&self.velocity_buffer, 0, unsafe {
+            velocity.align_to::<u8>().1
+        });
+        self.queue
+            .write_buffer(&self.density_buffer, 0, bytemuck::cast_slice(density));
+    }
 
-                c_pass.push_debug_group("velocity_calculation_compute");
-                c_pass.set_pipeline(&self.compute_pipeline);
-                c_pass.set_bind_group(0, &self.compute_uniform_bind_group, &[]);
-                c_pass.set_push_constants(0, bytemuck::cast_slice(&[PushConstants{ forced_velocity: self.forced_velocity, forced_density: self.forced_density }]));
-                c_pass.dispatch((VELOCITY_BUFFER_SIZE as u32 + 31) / 32, 1, 1);
-                c_pass.pop_debug_group();
+    // Writes the fields and remembers them, together with the current obstacles, as the state to
+    // go back to on reset
+    pub fn set_initial_conditions(&mut self, velocity: Vec<Vec2>, density: Vec<f32>) {
+        self.time = 0.0;
+        self.step_count = 0;
+        self.write_fields(&velocity, &density);
+        self.write_obstacles();
+        self.solver.clear_pressure(&self.queue);
+        self.clear_coupling_fields();
+        self.initial_conditions = InitialConditions {
+            velocity,
+            density,
+            obstacles: self.obstacles.clone(),
+        };
+    }
 
-                //graph_data.set_data(data_output, Some(&self._velocity_buffer));
-            },
+    // Velocity and density that reset goes back to
+    pub fn initial_fields(&self) -> (&[Vec2], &[f32]) {
+        (
+            &self.initial_conditions.velocity,
+            &self.initial_conditions.density,
+        )
+    }
+
+    pub fn reset(&mut self) {
+        self.time = 0.0;
+        self.step_count = 0;
+        self.obstacles = self.initial_conditions.obstacles.clone();
+        self.write_obstacles();
+        self.write_fields(
+            &self.initial_conditions.velocity,
+            &self.initial_conditions.density,
         );
+        self.solver.clear_pressure(&self.queue);
+        self.clear_coupling_fields();
     }
 
-    pub fn add_velocity_visualization_to_graph<'node>(
-        &'node self,
-        graph: &mut rend3::RenderGraph<'node>,
-    ) {
-        let mut builder = graph.add_node("velocity_field_visualize");
+    // Adds an obstacle that reset keeps, at its starting position
+    pub fn add_obstacle(&mut self, obstacle: Obstacle) {
+        self.initial_conditions.obstacles.push(obstacle.clone());
+        self.obstacles.push(obstacle);
+        self.write_obstacles();
+    }
+
+    // Removes an obstacle, also from the obstacles reset goes back to
+    pub fn remove_obstacle(&mut self, index: usize) {
+        if index < self.initial_conditions.obstacles.len() {
+            self.initial_conditions.obstacles.remove(index);
+        }
+        self.obstacles.remove(index);
+        self.write_obstacles();
+    }
 
-        let output_handle = builder.add_surface_output();
-        let _data_input_handle = builder.add_data_input::<_, wgpu::Buffer>("Fluid Fields");
+    // Should be called after changing the shape, motion or origin of obstacles between steps.
+    // Reset keeps the changed shapes and motions. The obstacles are placed and rasterized again so
+    // the change shows while paused.
+    pub fn obstacles_changed(&mut self) {
+        for (initial, obstacle) in self
+            .initial_conditions
+            .obstacles
+            .iter_mut()
+            .zip(&self.obstacles)
+        {
+            initial.shape = obstacle.shape.clone();
+            initial.motion = obstacle.motion;
+        }
+        for obstacle in &mut self.obstacles {
+            obstacle.place(self.time);
+        }
+        self.write_obstacles();
+    }
 
-        builder.build(
-            move |_pt, _renderer, encoder_or_pass, _temps, _ready, graph_data| {
-                let encoder = encoder_or_pass.get_encoder();
+    pub fn toggle_pause(&mut self) {
+        self.run_state = match self.run_state {
+            RunState::Running => RunState::Paused,
+            RunState::Paused | RunState::Stepping(_) => RunState::Running,
+        };
+    }
 
-                let output = graph_data.get_render_target(output_handle);
+    // Runs the given number of steps, then pauses
+    pub fn step(&mut self, steps: u32) {
+        if steps > 0 {
+            self.run_state = RunState::Stepping(steps);
+        }
+    }
 
-                let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                    color_attachments: &[wgpu::RenderPassColorAttachment {
-                        view: output,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                            store: true,
-                        },
-                    }],
-                    depth_stencil_attachment: None,
-                    label: Some("velocity_field_visualize_render_pass"),
+    // Whether a simulation step should run this frame. Counts down the pending steps.
+    pub fn consume_step(&mut self) -> bool {
+        match self.run_state {
+            RunState::Running => true,
+            RunState::Paused => false,
+            RunState::Stepping(steps) => {
+                self.run_state = if steps > 1 {
+                    RunState::Stepping(steps - 1)
+                } else {
+                    RunState::Paused
+                };
+                true
+            }
+        }
+    }
+
+    // Copies a GPU buffer to the CPU, waiting for all submitted work to finish
+    fn read_buffer(&self, buffer: &wgpu::Buffer, size: u64) -> Vec<f32> {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("fluid_fields_readback_encoder"),
+            });
+        encoder.copy_buffer_to_buffer(buffer, 0, &self.readback_buffer, 0, size);
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = self.readback_buffer.slice(0..size);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        self.device.poll(wgpu::Maintain::Wait);
+        futures::executor::block_on(mapping).unwrap();
+
+        let data = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        self.readback_buffer.unmap();
+        data
+    }
+
+    // Row major, same layout as in write_fields
+    pub fn read_velocity_field(&self) -> Vec<Vec2> {
+        self.read_buffer(
+            &self.velocity_buffer,
+            (self.cell_count() * std::mem::size_of::<Vec2>()) as u64,
+        )
+        .chunks_exact(2)
+        .map(|velocity| vec2(velocity[0], velocity[1]))
+        .collect()
+    }
+
+    pub fn read_density_field(&self) -> Vec<f32> {
+        self.read_buffer(
+            &self.density_buffer,
+            (self.cell_count() * std::mem::size_of::<f32>()) as u64,
+        )
+    }
+
+    // Pressure of the last projection, zero in solid cells
+    pub fn read_pressure_field(&self) -> Vec<f32> {
+        self.read_buffer(
+            self.solver.pressure_buffer(),
+            (self.cell_count() * std::mem::size_of::<f32>()) as u64,
+        )
+    }
+
+    // Field values of the cell containing the point in grid space, read back without waiting. None
+    // outside the domain and until the first read of the cell has arrived. Should be called once
+    // per frame while probing.
+    pub fn probe(&mut self, point: Vec2) -> Option<ProbeValue> {
+        if !(0.0..1.0).contains(&point.x) || !(0.0..1.0).contains(&point.y) {
+            return None;
+        }
+        let cell = (
+            (point.x * self.grid_size_x as f32) as usize,
+            (point.y * self.grid_size_y as f32) as usize,
+        );
+        self.probe.read(
+            &self.device,
+            &self.queue,
+            &self.velocity_buffer,
+            &self.density_buffer,
+            self.solver.pressure_buffer(),
+            (self.grid_size_x, self.grid_size_y),
+            cell,
+            self.step_count,
+        )
+    }
+
+    pub fn parameters(&self) -> Parameters {
+        Parameters {
+            time_step: self.time_step,
+            forced_velocity: self.forced_velocity,
+            forced_density: self.forced_density,
+            velocity_dissipation: self.velocity_dissipation,
+            density_dissipation: self.density_dissipation,
+            gravity: self.gravity,
+        }
+    }
+
+    pub fn set_parameters(&mut self, parameters: &Parameters) {
+        self.time_step = parameters.time_step;
+        self.forced_velocity = parameters.forced_velocity;
+        self.forced_density = parameters.forced_density;
+        self.velocity_dissipation = parameters.velocity_dissipation;
+        self.density_dissipation = parameters.density_dissipation;
+        self.gravity = parameters.gravity;
+    }
+
+    fn parameter_mut(&mut self, name: &str) -> Option<&mut f32> {
+        Some(match name {
+            "time" => &mut self.time,
+            "time_step" => &mut self.time_step,
+            "forced_velocity.x" => &mut self.forced_velocity.x,
+            "forced_velocity.y" => &mut self.forced_velocity.y,
+            "forced_density" => &mut self.forced_density,
+            "velocity_dissipation" => &mut self.velocity_dissipation,
+            "density_dissipation" => &mut self.density_dissipation,
+            "gravity.x" => &mut self.gravity.x,
+            "gravity.y" => &mut self.gravity.y,
+            _ => return None,
+        })
+    }
+
+    // Reads back all the fields from the GPU together with the parameters
+    pub fn snapshot(&self) -> Snapshot {
+        build_snapshot(
+            (self.grid_size_x, self.grid_size_y),
+            self.time,
+            self.step_count,
+            &self.parameters(),
+            &self.force_fields,
+            &self.obstacles,
+            &self.read_velocity_field(),
+            self.read_density_field(),
+        )
+    }
+
+    // The restored state also becomes the state to go back to on reset
+    pub fn restore_snapshot(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        if (snapshot.grid_size_x, snapshot.grid_size_y) != self.grid_size() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "snapshot grid size is {}x{}, expected {}x{}",
+                    snapshot.grid_size_x, snapshot.grid_size_y, self.grid_size_x, self.grid_size_y
+                ),
+            ));
+        }
+
+        let field = |name: &str, components: usize| {
+            snapshot.field(name, components).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "snapshot has no {} field with {} components",
+                        name, components
+                    ),
+                )
+            })
+        };
+        let velocity = field("velocity", 2)?
+            .chunks_exact(2)
+            .map(|velocity| vec2(velocity[0], velocity[1]))
+            .collect();
+        let density = field("density", 1)?.to_vec();
+
+        self.force_fields = snapshot.force_fields.clone();
+        self.obstacles = snapshot.obstacles.clone();
+        self.set_initial_conditions(velocity, density);
+        self.step_count = snapshot.step_count;
+        for (name, value) in &snapshot.parameters {
+            if let Some(parameter) = self.parameter_mut(name) {
+                *parameter = *value;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Number of steps since the initial conditions were set
+    pub fn step_count(&self) -> u64 {
+        self.step_count
+    }
+
+    // Simulation time since the initial conditions were set
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn export_vtk(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        write_vtk(
+            path.as_ref(),
+            (self.grid_size_x, self.grid_size_y),
+            &self.read_velocity_field(),
+            &self.read_density_field(),
+        )
+    }
+
+    // Velocity has the shape (grid size y, grid size x, 2) and density (grid size y, grid size x),
+    // with the first row at the bottom of the domain
+    pub fn export_npy(
+        &self,
+        velocity_path: impl AsRef<Path>,
+        density_path: impl AsRef<Path>,
+    ) -> io::Result<()> {
+        write_npy(
+            velocity_path.as_ref(),
+            density_path.as_ref(),
+            (self.grid_size_x, self.grid_size_y),
+            &self.read_velocity_field(),
+            &self.read_density_field(),
+        )
+    }
+
+    // Same layout as export_npy, stored as the velocity and density arrays of the archive
+    pub fn export_npz(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        write_npz(
+            path.as_ref(),
+            (self.grid_size_x, self.grid_size_y),
+            &self.read_velocity_field(),
+            &self.read_density_field(),
+        )
+    }
+
+    // Loads .npy arrays with the export_npy layout as the new initial conditions. Fields without a
+    // path start at zero.
+    pub fn load_npy_initial_conditions(
+        &mut self,
+        velocity_path: Option<&Path>,
+        density_path: Option<&Path>,
+    ) -> io::Result<()> {
+        let velocity = match velocity_path {
+            Some(path) => read_npy_field(path, &[self.grid_size_y, self.grid_size_x, 2])?
+                .chunks_exact(2)
+                .map(|velocity| vec2(velocity[0], velocity[1]))
+                .collect(),
+            None => vec![Vec2::ZERO; self.cell_count()],
+        };
+        let density = match density_path {
+            Some(path) => read_npy_field(path, &[self.grid_size_y, self.grid_size_x])?,
+            None => vec![0.0; self.cell_count()],
+        };
+
+        self.set_initial_conditions(velocity, density);
+        Ok(())
+    }
+
+    // Uses one channel of the image, resampled to the grid, as the initial density. The initial
+    // velocity is kept.
+    pub fn load_density_image(
+        &mut self,
+        path: impl AsRef<Path>,
+        channel: Channel,
+    ) -> io::Result<()> {
+        let image = image_import::read_png(path)?;
+        let density = image.resample(channel, self.grid_size_x, self.grid_size_y);
+        self.set_initial_conditions(self.initial_conditions.velocity.clone(), density);
+        Ok(())
+    }
+
+    // Adds a static obstacle covering the whole domain, solid wherever the image is bright and
+    // opaque. Replaces the obstacle from a previously loaded mask.
+    pub fn load_obstacle_mask(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mask = read_obstacle_mask(path.as_ref(), self.grid_size_x, self.grid_size_y)?;
+        self.obstacles
+            .retain(|obstacle| !matches!(obstacle.shape, Shape::Mask { .. }));
+        self.obstacles.push(mask);
+        self.set_initial_conditions(
+            self.initial_conditions.velocity.clone(),
+            self.initial_conditions.density.clone(),
+        );
+        Ok(())
+    }
+
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.snapshot().write(&mut writer)
+    }
+
+    pub fn load_snapshot(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let snapshot = Snapshot::read(&mut BufReader::new(File::open(path)?))?;
+        self.restore_snapshot(&snapshot)
+    }
+
+    // Advances the simulation clock and moves the obstacles, then rasterizes them for the next
+    // force pass. Should be called once per simulation step.
+    pub fn update(&mut self) {
+        self.time += self.time_step;
+        self.step_count += 1;
+
+        let has_dynamic_obstacles = self
+            .obstacles
+            .iter()
+            .any(|obstacle| matches!(obstacle.motion, Motion::Dynamic { .. }));
+        if has_dynamic_obstacles {
+            self.read_coupling_fields();
+        }
+
+        obstacles::update_all(
+            &mut self.obstacles,
+            &self.coupling_velocity,
+            &self.coupling_pressure,
+            (self.grid_size_x, self.grid_size_y),
+            self.gravity,
+            self.time,
+            self.time_step,
+        );
+
+        self.write_obstacles();
+    }
+
+    // Collects the copy of the velocity and pressure that has arrived, then starts copying the
+    // current ones if no copy is in flight
+    fn read_coupling_fields(&mut self) {
+        self.device.poll(wgpu::Maintain::Poll);
+        let initial_conditions_count = self.initial_conditions_count;
+        let cell_count = self.cell_count();
+        let fields = self.coupling_readback.poll(|recorded_count, data| {
+            (recorded_count == initial_conditions_count).then(|| {
+                let (velocity, pressure) =
+                    bytemuck::cast_slice::<u8, f32>(data).split_at(2 * cell_count);
+                let velocity = velocity
+                    .chunks_exact(2)
+                    .map(|velocity| vec2(velocity[0], velocity[1]))
+                    .collect();
+                (velocity, pressure.to_vec())
+            })
+        });
+        if let Some(Some((velocity, pressure))) = fields {
+            self.coupling_velocity = velocity;
+            self.coupling_pressure = pressure;
+        }
+
+        let recorded = self.coupling_readback.try_record(|buffer| {
+            let mut encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("coupling_readback_encoder"),
                 });
-                pass.push_debug_group("velocity_field_visualize");
-                pass.set_pipeline(&self.render_pipeline);
+            let velocity_size = (cell_count * std::mem::size_of::<Vec2>()) as u64;
+            encoder.copy_buffer_to_buffer(&self.velocity_buffer, 0, buffer, 0, velocity_size);
+            encoder.copy_buffer_to_buffer(
+                self.solver.pressure_buffer(),
+                0,
+                buffer,
+                velocity_size,
+                (cell_count * std::mem::size_of::<f32>()) as u64,
+            );
+            self.queue.submit(Some(encoder.finish()));
+            initial_conditions_count
+        });
+        if recorded {
+            // Starts mapping the copy that was just submitted
+            self.coupling_readback.poll(|_, _| ());
+        }
+    }
 
-                pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+    // Drops the velocity and pressure read back from the fields that were replaced, including a
+    // copy that is still in flight
+    fn clear_coupling_fields(&mut self) {
+        self.coupling_velocity.clear();
+        self.coupling_pressure.clear();
+        self.initial_conditions_count += 1;
+    }
 
-                pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-                pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-                pass.draw_indexed(0..6, 0, 0..(GRID_SIZE_X * GRID_SIZE_Y) as u32);
+    // Rasterizes the obstacles at their current positions into the mask the force pass and the
+    // visualizations read
+    fn write_obstacles(&self) {
+        let cells = obstacles::rasterize(&self.obstacles, self.grid_size_x, self.grid_size_y);
+        self.queue
+            .write_buffer(&self.obstacles_buffer, 0, bytemuck::cast_slice(&cells));
+    }
 
-                pass.pop_debug_group();
-            },
+    // Index of the topmost obstacle containing the point in grid space
+    pub fn obstacle_at(&self, point: Vec2) -> Option<usize> {
+        self.obstacles
+            .iter()
+            .rposition(|obstacle| obstacle.contains(point))
+    }
+
+    // Records the force pass of one simulation step
+    pub fn record_forces(&self, encoder: &mut wgpu::CommandEncoder) {
+        let force_fields: Vec<ForceFieldData> = self
+            .force_fields
+            .iter()
+            .take(MAX_FORCE_FIELDS)
+            .map(ForceFieldData::from)
+            .collect();
+        if !force_fields.is_empty() {
+            self.queue.write_buffer(
+                &self.force_fields_buffer,
+                0,
+                bytemuck::cast_slice(&force_fields),
+            );
+        }
+
+        let mut c_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("velocity_calculation_compute_pass"),
+        });
+
+        c_pass.push_debug_group("velocity_calculation_compute");
+        c_pass.set_pipeline(&self.compute_pipeline);
+        c_pass.set_bind_group(0, &self.compute_uniform_bind_group, &[]);
+        c_pass.set_push_constants(
+            0,
+            bytemuck::cast_slice(&[PushConstants {
+                forced_velocity: self.forced_velocity,
+                forced_density: self.forced_density,
+                time_step: self.time_step,
+                velocity_dissipation: self.velocity_dissipation,
+                density_dissipation: self.density_dissipation,
+                gravity: self.gravity,
+                force_field_count: force_fields.len() as u32,
+            }]),
         );
+        c_pass.dispatch(
+            (self.grid_size_x as u32).div_ceil(8),
+            (self.grid_size_y as u32).div_ceil(8),
+            1,
+        );
+        c_pass.pop_debug_group();
+    }
+
+    // Advances the simulation by one step and submits it right away, for callers without a render
+    // loop. Like a frame of the app it also records and collects the diagnostics.
+    pub fn run_step(&mut self) {
+        self.update();
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("fluid_step_encoder"),
+            });
+        self.record_step(&mut encoder);
+        self.queue.submit(Some(encoder.finish()));
+        self.poll_diagnostics();
+    }
+
+    // Records the reductions of the diagnostics for the current step
+    pub fn record_diagnostics(&self, encoder: &mut wgpu::CommandEncoder) {
+        self.diagnostics.record(encoder, self.step_count, self.time);
+    }
+
+    // Collects the diagnostics that finished since the last call. Should be called once per frame,
+    // after the frame has been submitted.
+    pub fn poll_diagnostics(&mut self) {
+        self.diagnostics.poll(&self.device);
+    }
+
+    // Diagnostics of the most recent steps, oldest first. Steps can be missing when the readbacks
+    // fall behind.
+    pub fn diagnostics(&self) -> &VecDeque<diagnostics::Sample> {
+        self.diagnostics.history()
+    }
+
+    // Starts timing the passes of the graph nodes for a new frame. Should be called before the
+    // nodes are added to the graph.
+    pub fn begin_profiling_frame(&mut self) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.begin_frame();
+        }
+    }
+
+    // Collects the pass timings that finished since the last call. Should be called once per
+    // frame, after the frame has been submitted.
+    pub fn end_profiling_frame(&mut self) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.end_frame(&self.device, &self.queue);
+        }
+    }
+
+    // Pass timings of the most recent frames, oldest first. None if the device does not support
+    // timestamp queries.
+    pub fn profiler_history(&self) -> Option<&VecDeque<FrameTimings>> {
+        self.profiler.as_ref().map(GpuProfiler::history)
+    }
+
+    fn profile_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        name: &'static str,
+        record: impl FnOnce(&mut wgpu::CommandEncoder),
+    ) {
+        let query = self
+            .profiler
+            .as_ref()
+            .and_then(|profiler| profiler.begin_pass(encoder, name));
+        record(encoder);
+        if let Some(profiler) = &self.profiler {
+            profiler.end_pass(encoder, query);
+        }
+    }
+
+    // Records one simulation step into the encoder: the force pass, advection and the pressure
+    // projection, followed by the diagnostics. update has to be called for the step first.
+    pub fn record_step(&self, encoder: &mut wgpu::CommandEncoder) {
+        self.profile_pass(encoder, "forces", |encoder| self.record_forces(encoder));
+        self.profile_pass(encoder, "advection", |encoder| {
+            self.solver.record_advection(
+                encoder,
+                &self.velocity_buffer,
+                &self.density_buffer,
+                self.time_step,
+            )
+        });
+        self.profile_pass(encoder, "projection", |encoder| {
+            self.solver.record_projection(encoder, self.time_step)
+        });
+        self.profile_pass(encoder, "diagnostics", |encoder| {
+            self.record_diagnostics(encoder)
+        });
     }
 
-    pub fn add_density_visualization_to_graph<'node>(
-        &'node self,
-        graph: &mut rend3::RenderGraph<'node>,
+    // Records the visualization into the output, which has to have the surface format
+    pub fn record_visualization(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        visualization: Visualization,
+        output: &wgpu::TextureView,
     ) {
-        let mut builder = graph.add_node("density_field_visualize");
+        match visualization {
+            Visualization::Density => {
+                self.profile_pass(encoder, "density visualization", |encoder| {
+                    self.record_density_visualization(encoder, output)
+                })
+            }
+            Visualization::Velocity => {
+                self.profile_pass(encoder, "velocity visualization", |encoder| {
+                    self.record_velocity_visualization(encoder, output)
+                })
+            }
+        }
+    }
 
-        let output_handle = builder.add_surface_output();
-        let _data_input_handle = builder.add_data_input::<_, wgpu::Buffer>("Fluid Fields");
+    fn record_velocity_visualization(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+            label: Some("velocity_field_visualize_render_pass"),
+        });
+        pass.push_debug_group("velocity_field_visualize");
+        pass.set_pipeline(&self.render_pipeline);
 
-        builder.build(
-            move |_pt, _renderer, encoder_or_pass, _temps, _ready, graph_data| {
-                let encoder = encoder_or_pass.get_encoder();
+        pass.set_bind_group(0, &self.uniform_bind_group, &[]);
 
-                let output = graph_data.get_render_target(output_handle);
+        pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.draw_indexed(0..6, 0, 0..self.cell_count() as u32);
 
-                let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                    color_attachments: &[wgpu::RenderPassColorAttachment {
-                        view: output,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                            store: true,
-                        },
-                    }],
-                    depth_stencil_attachment: None,
-                    label: Some("density_field_visualize_render_pass"),
-                });
-                pass.push_debug_group("density_field_visualize");
-                pass.set_pipeline(&self.density_render_pipeline);
+        pass.pop_debug_group();
+    }
+
+    fn record_density_visualization(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+            label: Some("density_field_visualize_render_pass"),
+        });
+        pass.push_debug_group("density_field_visualize");
+        pass.set_pipeline(&self.density_render_pipeline);
+
+        pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+
+        pass.draw(0..3, 0..1);
+
+        pass.pop_debug_group();
+    }
 
-                pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+    // Renders the visualization into an offscreen texture of the given size and returns the pixels
+    // as tightly packed RGBA8, top row first. Only 8 bit RGBA and BGRA surface formats can be
+    // captured.
+    pub fn capture_visualization(
+        &self,
+        visualization: Visualization,
+        width: u32,
+        height: u32,
+    ) -> io::Result<Vec<u8>> {
+        let is_bgra = match self.surface_format {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            format => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("can not capture the {:?} surface format", format),
+                ))
+            }
+        };
 
-                pass.draw(0..3, 0..1);
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("fluid_capture_texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.surface_format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
 
-                pass.pop_debug_group();
+        // Rows in the buffer have to be aligned for the copy
+        let bytes_per_row = width * 4;
+        let padded_bytes_per_row = bytes_per_row.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("fluid_capture_readback_buffer"),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            size: (padded_bytes_per_row * height) as u64,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("fluid_capture_encoder"),
+            });
+        match visualization {
+            Visualization::Density => self.record_density_visualization(&mut encoder, &view),
+            Visualization::Velocity => self.record_velocity_visualization(&mut encoder, &view),
+        }
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
             },
         );
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        self.device.poll(wgpu::Maintain::Wait);
+        futures::executor::block_on(mapping).unwrap();
+
+        let data = slice.get_mapped_range();
+        let mut pixels = Vec::with_capacity((bytes_per_row * height) as usize);
+        for row in data.chunks(padded_bytes_per_row as usize) {
+            for pixel in row[..bytes_per_row as usize].chunks_exact(4) {
+                if is_bgra {
+                    pixels.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+                } else {
+                    pixels.extend_from_slice(pixel);
+                }
+            }
+        }
+        Ok(pixels)
     }
 }
@@ -0,0 +1,42 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter},
+    path::Path,
+};
+
+use crate::fluid_simulator::{FluidSimulator, Visualization};
+
+pub fn write_png(path: impl AsRef<Path>, width: u32, height: u32, rgba: &[u8]) -> io::Result<()> {
+    encode_png(File::create(path)?, width, height, rgba)
+}
+
+fn encode_png(file: File, width: u32, height: u32, rgba: &[u8]) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    Ok(())
+}
+
+// Renders the visualization offscreen and writes it into the directory as a PNG file with the
+// frame number. The caller counts the frames, as the step count goes back to zero on reset. Fails
+// instead of overwriting a frame that is already there, from an earlier recording into the same
+// directory.
+pub fn save_frame(
+    fluid_simulator: &FluidSimulator,
+    visualization: Visualization,
+    width: u32,
+    height: u32,
+    directory: impl AsRef<Path>,
+    frame: u64,
+) -> io::Result<()> {
+    std::fs::create_dir_all(&directory)?;
+    let pixels = fluid_simulator.capture_visualization(visualization, width, height)?;
+    let path = directory.as_ref().join(format!("frame_{:06}.png", frame));
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    encode_png(file, width, height, &pixels)
+}
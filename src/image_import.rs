@@ -0,0 +1,130 @@
+use std::{fs::File, io, path::Path};
+
+use glam::{vec2, Vec2};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+    Alpha,
+    #[default]
+    Luminance,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 5] = [
+        Channel::Red,
+        Channel::Green,
+        Channel::Blue,
+        Channel::Alpha,
+        Channel::Luminance,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Channel::Red => "red",
+            Channel::Green => "green",
+            Channel::Blue => "blue",
+            Channel::Alpha => "alpha",
+            Channel::Luminance => "luminance",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|channel| channel.name() == name)
+    }
+}
+
+// 8 bit RGBA image, top row first
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub rgba: Vec<u8>,
+}
+
+pub fn read_png(path: impl AsRef<Path>) -> io::Result<Image> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+    let pixels = &buffer[..info.buffer_size()];
+
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => pixels.to_vec(),
+        png::ColorType::Rgb => pixels
+            .chunks_exact(3)
+            .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => pixels
+            .chunks_exact(2)
+            .flat_map(|pixel| [pixel[0], pixel[0], pixel[0], pixel[1]])
+            .collect(),
+        png::ColorType::Grayscale => pixels
+            .iter()
+            .flat_map(|&value| [value, value, value, 255])
+            .collect(),
+        png::ColorType::Indexed => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "indexed png was not expanded",
+            ))
+        }
+    };
+
+    Ok(Image {
+        width: info.width as usize,
+        height: info.height as usize,
+        rgba,
+    })
+}
+
+impl Image {
+    fn texel(&self, channel: Channel, x: usize, y: usize) -> f32 {
+        let pixel = &self.rgba[(x + y * self.width) * 4..][..4];
+        let value = |index: usize| pixel[index] as f32 / 255.0;
+        match channel {
+            Channel::Red => value(0),
+            Channel::Green => value(1),
+            Channel::Blue => value(2),
+            Channel::Alpha => value(3),
+            Channel::Luminance => 0.2126 * value(0) + 0.7152 * value(1) + 0.0722 * value(2),
+        }
+    }
+
+    // Bilinearly filtered channel value from 0 to 1 at a position in grid space, where (0, 0) is
+    // the bottom left corner of the image
+    pub fn sample(&self, channel: Channel, position: Vec2) -> f32 {
+        let size = vec2(self.width as f32, self.height as f32);
+        let texel = vec2(position.x, 1.0 - position.y) * size - 0.5;
+        let texel = texel.clamp(Vec2::ZERO, size - 1.0);
+
+        let (x0, y0) = (texel.x.floor() as usize, texel.y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let fraction = texel - vec2(x0 as f32, y0 as f32);
+
+        let top = self.texel(channel, x0, y0) * (1.0 - fraction.x)
+            + self.texel(channel, x1, y0) * fraction.x;
+        let bottom = self.texel(channel, x0, y1) * (1.0 - fraction.x)
+            + self.texel(channel, x1, y1) * fraction.x;
+        top * (1.0 - fraction.y) + bottom * fraction.y
+    }
+
+    // Samples the channel at every cell center, row major starting from the bottom left cell
+    pub fn resample(&self, channel: Channel, grid_size_x: usize, grid_size_y: usize) -> Vec<f32> {
+        let mut values = Vec::with_capacity(grid_size_x * grid_size_y);
+        for y in 0..grid_size_y {
+            for x in 0..grid_size_x {
+                let position =
+                    (vec2(x as f32, y as f32) + 0.5) / vec2(grid_size_x as f32, grid_size_y as f32);
+                values.push(self.sample(channel, position));
+            }
+        }
+        values
+    }
+}
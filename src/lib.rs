@@ -0,0 +1,31 @@
+// GPU fluid simulator that can be embedded into other wgpu or rend3 applications. FluidSimulator
+// owns the fields and parameters, records the simulation and visualization passes into a
+// CommandEncoder, and reads the fields back for export. The rend3 feature, on by default, adds
+// constructing it from a rend3 Renderer and adding its passes to a RenderGraph. The interactive app
+// built on top of it is the fluid_simulator example, and Python bindings live in the python crate.
+
+pub mod cpu_solver;
+pub mod device;
+pub mod diagnostics;
+pub mod fluid_simulator;
+pub mod image_capture;
+pub mod image_import;
+mod numpy_io;
+pub mod obstacles;
+pub mod probe;
+pub mod profiler;
+mod readback;
+#[cfg(feature = "rend3")]
+mod rend3_adapter;
+pub mod scenarios;
+pub mod scene;
+mod shaders;
+pub mod snapshot;
+mod solver;
+pub mod verification;
+mod vtk_export;
+
+pub use crate::fluid_simulator::{
+    FluidSimulator, ForceField, RunState, Visualization, DEFAULT_GRID_SIZE_X, DEFAULT_GRID_SIZE_Y,
+    MAX_FORCE_FIELDS, MAX_GRID_SIZE,
+};
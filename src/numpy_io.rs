@@ -0,0 +1,256 @@
+use std::io::{self, Read, Seek, Write};
+
+// Minimal reader and writer for the NumPy .npy format, version 1.0, and uncompressed .npz archives.
+// Arrays are always written as little endian float32 in C order. Reading also accepts float64, which
+// is what NumPy creates by default.
+const MAGIC: &[u8; 6] = b"\x93NUMPY";
+// Headers of version 2 and 3 can be up to 4 GiB, real ones are well below this
+const MAX_HEADER_LENGTH: usize = 1 << 20;
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+pub fn write_npy(writer: &mut impl Write, shape: &[usize], data: &[f32]) -> io::Result<()> {
+    assert_eq!(shape.iter().product::<usize>(), data.len());
+
+    let shape = match shape {
+        [length] => format!("({},)", length),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(|dimension| dimension.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': {}, }}",
+        shape
+    );
+    // The data has to start at a multiple of 64 bytes, counting the magic, version and header length
+    let unpadded_length = MAGIC.len() + 2 + 2 + header.len() + 1;
+    header.push_str(&" ".repeat((64 - unpadded_length % 64) % 64));
+    header.push('\n');
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[1, 0])?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    for value in data {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+// Value of a key in the header dictionary, e.g. "'<f4'" for 'descr'
+fn header_value<'a>(header: &'a str, key: &str) -> io::Result<&'a str> {
+    let key = format!("'{}':", key);
+    let start = header
+        .find(&key)
+        .ok_or_else(|| invalid_data(format!("npy header has no {}", key)))?
+        + key.len();
+    let value = header[start..].trim_start();
+    let end = if value.starts_with('(') {
+        value.find(')').map(|end| end + 1)
+    } else {
+        value.find(',')
+    }
+    .ok_or_else(|| invalid_data("malformed npy header"))?;
+    Ok(value[..end].trim())
+}
+
+// Returns the data converted to f32. The shape is checked against the expected one before any data
+// is read.
+pub fn read_npy(reader: &mut impl Read, expected_shape: &[usize]) -> io::Result<Vec<f32>> {
+    let mut magic = [0; 6];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a npy file"));
+    }
+
+    let mut version = [0; 2];
+    reader.read_exact(&mut version)?;
+    let header_length = match version[0] {
+        1 => {
+            let mut length = [0; 2];
+            reader.read_exact(&mut length)?;
+            u16::from_le_bytes(length) as usize
+        }
+        2 | 3 => {
+            let mut length = [0; 4];
+            reader.read_exact(&mut length)?;
+            u32::from_le_bytes(length) as usize
+        }
+        major => {
+            return Err(invalid_data(format!(
+                "unsupported npy version {}.{}",
+                major, version[1]
+            )))
+        }
+    };
+    if header_length > MAX_HEADER_LENGTH {
+        return Err(invalid_data(format!(
+            "npy header of {} bytes is too long",
+            header_length
+        )));
+    }
+    let mut header = vec![0; header_length];
+    reader.read_exact(&mut header)?;
+    let header = String::from_utf8_lossy(&header);
+
+    if header_value(&header, "fortran_order")? != "False" {
+        return Err(invalid_data("fortran ordered npy arrays are not supported"));
+    }
+    let shape = header_value(&header, "shape")?
+        .trim_matches(|c| c == '(' || c == ')')
+        .split(',')
+        .map(str::trim)
+        .filter(|dimension| !dimension.is_empty())
+        .map(|dimension| {
+            dimension
+                .parse()
+                .map_err(|_| invalid_data(format!("invalid npy dimension {}", dimension)))
+        })
+        .collect::<io::Result<Vec<usize>>>()?;
+    if shape != expected_shape {
+        return Err(invalid_data(format!(
+            "npy array has shape {:?}, expected {:?}",
+            shape, expected_shape
+        )));
+    }
+    let length = shape
+        .iter()
+        .try_fold(1usize, |length, &dimension| length.checked_mul(dimension))
+        .ok_or_else(|| invalid_data("npy array is too large"))?;
+    let byte_length = |size: usize| {
+        length
+            .checked_mul(size)
+            .ok_or_else(|| invalid_data("npy array is too large"))
+    };
+
+    let descr = header_value(&header, "descr")?.trim_matches(|c| c == '\'' || c == '"');
+    let data = match descr {
+        "<f4" => {
+            let mut bytes = vec![0; byte_length(4)?];
+            reader.read_exact(&mut bytes)?;
+            bytes
+                .chunks_exact(4)
+                .map(|value| f32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+                .collect()
+        }
+        "<f8" => {
+            let mut bytes = vec![0; byte_length(8)?];
+            reader.read_exact(&mut bytes)?;
+            bytes
+                .chunks_exact(8)
+                .map(|value| {
+                    let mut double = [0; 8];
+                    double.copy_from_slice(value);
+                    f64::from_le_bytes(double) as f32
+                })
+                .collect()
+        }
+        _ => {
+            return Err(invalid_data(format!(
+                "unsupported npy data type {}, expected <f4 or <f8",
+                descr
+            )))
+        }
+    };
+
+    Ok(data)
+}
+
+// Writes each array as <name>.npy into an uncompressed zip archive, loadable with numpy.load
+pub fn write_npz<W: Write + Seek>(
+    writer: W,
+    arrays: &[(&str, &[usize], &[f32])],
+) -> io::Result<()> {
+    let mut zip = zip::ZipWriter::new(writer);
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    for (name, shape, data) in arrays {
+        zip.start_file(format!("{}.npy", name), options)?;
+        write_npy(&mut zip, shape, data)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn npy_round_trip() {
+        let data = (0..24).map(|i| i as f32 * 0.5).collect::<Vec<_>>();
+        let mut bytes = Vec::new();
+        write_npy(&mut bytes, &[3, 4, 2], &data).unwrap();
+        // The data starts at a multiple of 64 bytes
+        assert_eq!((bytes.len() - data.len() * 4) % 64, 0);
+        assert_eq!(read_npy(&mut bytes.as_slice(), &[3, 4, 2]).unwrap(), data);
+
+        let mut bytes = Vec::new();
+        write_npy(&mut bytes, &[5], &data[..5]).unwrap();
+        assert_eq!(read_npy(&mut bytes.as_slice(), &[5]).unwrap(), &data[..5]);
+    }
+
+    #[test]
+    fn reads_float64() {
+        let header = "{'descr': '<f8', 'fortran_order': False, 'shape': (2,), }\n";
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[1, 0]);
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(&1.5f64.to_le_bytes());
+        bytes.extend_from_slice(&(-2.0f64).to_le_bytes());
+        assert_eq!(read_npy(&mut bytes.as_slice(), &[2]).unwrap(), [1.5, -2.0]);
+    }
+
+    #[test]
+    fn rejects_unexpected_shape() {
+        let mut bytes = Vec::new();
+        write_npy(&mut bytes, &[2, 3], &[0.0; 6]).unwrap();
+        let error = read_npy(&mut bytes.as_slice(), &[3, 2]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // A header claiming a huge array fails on the shape, before anything is allocated
+        let header = format!(
+            "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}), }}\n",
+            usize::MAX,
+            usize::MAX
+        );
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[1, 0]);
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        let error = read_npy(&mut bytes.as_slice(), &[2, 3]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn npz_round_trip() {
+        let velocity = [1.0, 2.0, 3.0, 4.0];
+        let density = [0.25, 0.75];
+        let mut archive = Cursor::new(Vec::new());
+        write_npz(
+            &mut archive,
+            &[
+                ("velocity", &[1, 2, 2], &velocity),
+                ("density", &[1, 2], &density),
+            ],
+        )
+        .unwrap();
+
+        let mut archive = zip::ZipArchive::new(archive).unwrap();
+        let mut file = archive.by_name("velocity.npy").unwrap();
+        assert_eq!(read_npy(&mut file, &[1, 2, 2]).unwrap(), velocity);
+        drop(file);
+        let mut file = archive.by_name("density.npy").unwrap();
+        assert_eq!(read_npy(&mut file, &[1, 2]).unwrap(), density);
+    }
+}
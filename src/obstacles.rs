@@ -0,0 +1,455 @@
+use glam::{vec2, Mat2, Vec2};
+use serde::{Deserialize, Serialize};
+
+// Largest mask width or height accepted from files
+pub const MAX_MASK_SIZE: usize = 4096;
+
+// Shapes are defined in the local space of the obstacle, in grid space units
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Shape {
+    Circle {
+        radius: f32,
+    },
+    Box {
+        half_extents: Vec2,
+    },
+    // Counter-clockwise list of vertices
+    Polygon {
+        vertices: Vec<Vec2>,
+    },
+    // Solid/fluid bitmap stretched over the box, row major starting from the bottom left cell
+    Mask {
+        half_extents: Vec2,
+        width: usize,
+        height: usize,
+        solid: Vec<bool>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Motion {
+    Static,
+    // Moves back and forth around the origin
+    Oscillate { amplitude: Vec2, frequency: f32 },
+    // Moves in a circle around the origin
+    Orbit { radius: f32, frequency: f32 },
+    // Spins in place around the origin
+    Rotate { angular_velocity: f32 },
+    // Rigid body pushed around by the fluid and gravity. The drag scales the force from the
+    // velocity difference between the fluid and the obstacle surface.
+    Dynamic { mass: f32, inertia: f32, drag: f32 },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Obstacle {
+    pub shape: Shape,
+    pub motion: Motion,
+    // Position the scripted motion is relative to
+    pub origin: Vec2,
+    // Rotation before the first step, which scenes are saved with
+    pub initial_rotation: f32,
+    pub position: Vec2,
+    pub rotation: f32,
+    pub velocity: Vec2,
+    pub angular_velocity: f32,
+    // When set the obstacle follows this point instead of its scripted motion
+    pub drag_target: Option<Vec2>,
+}
+
+// Matches the ObstacleCellData struct in velocity_calculations.hlsl
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub(crate) struct ObstacleCellData {
+    pub(crate) velocity: Vec2,
+    pub(crate) solid: u32,
+    _padding: u32,
+}
+
+unsafe impl bytemuck::Pod for ObstacleCellData {}
+unsafe impl bytemuck::Zeroable for ObstacleCellData {}
+
+// Masks need at least one cell to sample
+pub(crate) fn check_mask_size(width: usize, height: usize) -> Result<(), String> {
+    if !(1..=MAX_MASK_SIZE).contains(&width) || !(1..=MAX_MASK_SIZE).contains(&height) {
+        return Err(format!("unsupported mask size {}x{}", width, height));
+    }
+    Ok(())
+}
+
+impl Shape {
+    // Checks what the type system does not, for shapes read from files
+    pub fn validate(&self) -> Result<(), String> {
+        if let Shape::Mask {
+            width,
+            height,
+            solid,
+            ..
+        } = self
+        {
+            check_mask_size(*width, *height)?;
+            if solid.len() != width * height {
+                return Err(format!(
+                    "{}x{} mask has {} cells",
+                    width,
+                    height,
+                    solid.len()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Motion {
+    // Dynamic obstacles divide the fluid force by their mass and inertia
+    pub fn validate(&self) -> Result<(), String> {
+        if let Motion::Dynamic { mass, inertia, .. } = *self {
+            let positive = |value: f32| value.is_finite() && value > 0.0;
+            if !positive(mass) || !positive(inertia) {
+                return Err(format!(
+                    "dynamic obstacle has mass {} and inertia {}, both must be positive",
+                    mass, inertia
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Obstacle {
+    pub fn new(shape: Shape, position: Vec2, motion: Motion) -> Self {
+        Self {
+            shape,
+            motion,
+            origin: position,
+            initial_rotation: 0.0,
+            position,
+            rotation: 0.0,
+            velocity: Vec2::ZERO,
+            angular_velocity: 0.0,
+            drag_target: None,
+        }
+    }
+
+    // Offset from the origin and velocity of the scripted motion at the given time
+    fn scripted_offset(&self, time: f32) -> (Vec2, Vec2) {
+        use std::f32::consts::TAU;
+
+        match self.motion {
+            Motion::Static | Motion::Rotate { .. } | Motion::Dynamic { .. } => {
+                (Vec2::ZERO, Vec2::ZERO)
+            }
+            Motion::Oscillate {
+                amplitude,
+                frequency,
+            } => {
+                let phase = TAU * frequency * time;
+                (
+                    amplitude * phase.sin(),
+                    amplitude * TAU * frequency * phase.cos(),
+                )
+            }
+            Motion::Orbit { radius, frequency } => {
+                let phase = TAU * frequency * time;
+                let speed = radius * TAU * frequency;
+                (
+                    vec2(phase.cos(), phase.sin()) * radius,
+                    vec2(-phase.sin(), phase.cos()) * speed,
+                )
+            }
+        }
+    }
+
+    pub fn update(&mut self, time: f32, time_step: f32) {
+        if let Some(target) = self.drag_target {
+            let (offset, _) = self.scripted_offset(time);
+            self.velocity = (target - self.position) / time_step;
+            self.angular_velocity = 0.0;
+            self.position = target;
+            // Continue the scripted motion from where the obstacle was dropped
+            self.origin = target - offset;
+            return;
+        }
+
+        if let Motion::Dynamic { .. } = self.motion {
+            self.position += self.velocity * time_step;
+            self.rotation += self.angular_velocity * time_step;
+
+            // Stop at the walls of the domain
+            let radius = self.bounding_radius();
+            let clamped = self
+                .position
+                .clamp(Vec2::splat(radius), Vec2::splat(1.0 - radius));
+            if clamped.x != self.position.x {
+                self.velocity.x = 0.0;
+            }
+            if clamped.y != self.position.y {
+                self.velocity.y = 0.0;
+            }
+            self.position = clamped;
+            self.origin = clamped;
+            return;
+        }
+
+        self.place(time);
+        self.rotation += self.angular_velocity * time_step;
+    }
+
+    // Moves the obstacle to where its motion puts it at the given time without advancing it, for
+    // when the origin or motion was edited between steps. Dynamic obstacles stay at the origin,
+    // which follows their position while they move.
+    pub fn place(&mut self, time: f32) {
+        if let Motion::Dynamic { .. } = self.motion {
+            self.position = self.origin;
+            return;
+        }
+
+        let (offset, scripted_velocity) = self.scripted_offset(time);
+        self.position = self.origin + offset;
+        self.velocity = scripted_velocity;
+        self.angular_velocity = match self.motion {
+            Motion::Rotate { angular_velocity } => angular_velocity,
+            _ => 0.0,
+        };
+    }
+
+    // Only has an effect on dynamic obstacles
+    pub fn apply_force(&mut self, force: Vec2, torque: f32, time_step: f32) {
+        if let Motion::Dynamic { mass, inertia, .. } = self.motion {
+            self.velocity += force / mass * time_step;
+            self.angular_velocity += torque / inertia * time_step;
+        }
+    }
+
+    fn bounding_radius(&self) -> f32 {
+        match &self.shape {
+            Shape::Circle { radius } => *radius,
+            Shape::Box { half_extents } => half_extents.length(),
+            Shape::Polygon { vertices } => vertices
+                .iter()
+                .map(|vertex| vertex.length())
+                .fold(0.0, f32::max),
+            Shape::Mask { half_extents, .. } => half_extents.length(),
+        }
+    }
+
+    pub fn contains(&self, point: Vec2) -> bool {
+        let local = Mat2::from_angle(-self.rotation) * (point - self.position);
+        match &self.shape {
+            Shape::Circle { radius } => local.length_squared() <= radius * radius,
+            Shape::Box { half_extents } => {
+                local.x.abs() <= half_extents.x && local.y.abs() <= half_extents.y
+            }
+            Shape::Polygon { vertices } => {
+                // Even-odd rule
+                let mut inside = false;
+                for (index, a) in vertices.iter().enumerate() {
+                    let b = vertices[(index + 1) % vertices.len()];
+                    if (a.y > local.y) != (b.y > local.y)
+                        && local.x < a.x + (local.y - a.y) / (b.y - a.y) * (b.x - a.x)
+                    {
+                        inside = !inside;
+                    }
+                }
+                inside
+            }
+            Shape::Mask {
+                half_extents,
+                width,
+                height,
+                solid,
+            } => {
+                if local.x.abs() > half_extents.x || local.y.abs() > half_extents.y {
+                    return false;
+                }
+                let uv = (local / *half_extents + 1.0) / 2.0;
+                let x = ((uv.x * *width as f32) as usize).min(width - 1);
+                let y = ((uv.y * *height as f32) as usize).min(height - 1);
+                solid[x + y * width]
+            }
+        }
+    }
+
+    // Rigid body velocity of the obstacle at a point
+    pub fn velocity_at(&self, point: Vec2) -> Vec2 {
+        let arm = point - self.position;
+        self.velocity + vec2(-arm.y, arm.x) * self.angular_velocity
+    }
+}
+
+// Advances the obstacles to the given time. Dynamic obstacles are pushed by gravity and by the
+// fluid, which has no effect while the fields are empty.
+pub(crate) fn update_all(
+    obstacles: &mut [Obstacle],
+    velocity_field: &[Vec2],
+    pressure_field: &[f32],
+    (grid_size_x, grid_size_y): (usize, usize),
+    gravity: Vec2,
+    time: f32,
+    time_step: f32,
+) {
+    for obstacle in obstacles {
+        if let Motion::Dynamic { mass, .. } = obstacle.motion {
+            let (force, torque) = if velocity_field.is_empty() || pressure_field.is_empty() {
+                (Vec2::ZERO, 0.0)
+            } else {
+                fluid_force(
+                    obstacle,
+                    velocity_field,
+                    pressure_field,
+                    grid_size_x,
+                    grid_size_y,
+                )
+            };
+            obstacle.apply_force(force + gravity * mass, torque, time_step);
+        }
+        obstacle.update(time, time_step);
+    }
+}
+
+// Marks every cell whose center is inside an obstacle as solid, storing the obstacle velocity as the
+// boundary velocity for that cell. Later obstacles win where they overlap.
+pub(crate) fn rasterize(
+    obstacles: &[Obstacle],
+    grid_size_x: usize,
+    grid_size_y: usize,
+) -> Vec<ObstacleCellData> {
+    let mut cells = vec![
+        ObstacleCellData {
+            velocity: Vec2::ZERO,
+            solid: 0,
+            _padding: 0,
+        };
+        grid_size_x * grid_size_y
+    ];
+
+    for obstacle in obstacles {
+        for y in 0..grid_size_y {
+            for x in 0..grid_size_x {
+                let position =
+                    (vec2(x as f32, y as f32) + 0.5) / vec2(grid_size_x as f32, grid_size_y as f32);
+                if obstacle.contains(position) {
+                    cells[x + y * grid_size_x] = ObstacleCellData {
+                        velocity: obstacle.velocity_at(position),
+                        solid: 1,
+                        _padding: 0,
+                    };
+                }
+            }
+        }
+    }
+
+    cells
+}
+
+// Force and torque from the fluid on a dynamic obstacle, summed over the faces between its solid
+// cells and the neighbouring fluid cells. Each face adds the pressure of the fluid cell pushing
+// along the normal, for a fluid density of one, and the shear from the velocity difference across
+// the face scaled by the drag.
+pub(crate) fn fluid_force(
+    obstacle: &Obstacle,
+    velocity_field: &[Vec2],
+    pressure_field: &[f32],
+    grid_size_x: usize,
+    grid_size_y: usize,
+) -> (Vec2, f32) {
+    let drag = match obstacle.motion {
+        Motion::Dynamic { drag, .. } => drag,
+        _ => return (Vec2::ZERO, 0.0),
+    };
+
+    let grid_size = vec2(grid_size_x as f32, grid_size_y as f32);
+    let cell_position = |x: i32, y: i32| (vec2(x as f32, y as f32) + 0.5) / grid_size;
+
+    let mut force = Vec2::ZERO;
+    let mut torque = 0.0;
+    for y in 0..grid_size_y as i32 {
+        for x in 0..grid_size_x as i32 {
+            if !obstacle.contains(cell_position(x, y)) {
+                continue;
+            }
+
+            for (offset_x, offset_y) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                let (neighbour_x, neighbour_y) = (x + offset_x, y + offset_y);
+                if neighbour_x < 0
+                    || neighbour_y < 0
+                    || neighbour_x >= grid_size_x as i32
+                    || neighbour_y >= grid_size_y as i32
+                    || obstacle.contains(cell_position(neighbour_x, neighbour_y))
+                {
+                    continue;
+                }
+
+                let face = (cell_position(x, y) + cell_position(neighbour_x, neighbour_y)) / 2.0;
+                let face_length = if offset_x != 0 {
+                    1.0 / grid_size.y
+                } else {
+                    1.0 / grid_size.x
+                };
+                let neighbour = neighbour_x as usize + neighbour_y as usize * grid_size_x;
+                let normal = vec2(offset_x as f32, offset_y as f32);
+                let pressure_force = -pressure_field[neighbour] * normal * face_length;
+                let shear_force =
+                    (velocity_field[neighbour] - obstacle.velocity_at(face)) * drag * face_length;
+                let face_force = pressure_force + shear_force;
+
+                force += face_force;
+                torque += (face - obstacle.position).perp_dot(face_force);
+            }
+        }
+    }
+
+    (force, torque)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn place_follows_an_edited_origin() {
+        let mut obstacle = Obstacle::new(
+            Shape::Circle { radius: 0.1 },
+            vec2(0.5, 0.5),
+            Motion::Oscillate {
+                amplitude: vec2(0.2, 0.0),
+                frequency: 1.0,
+            },
+        );
+        obstacle.update(0.25, 0.25);
+        assert!(obstacle.position.abs_diff_eq(vec2(0.7, 0.5), 1e-6));
+
+        obstacle.origin = vec2(0.3, 0.4);
+        obstacle.place(0.25);
+        assert!(obstacle.position.abs_diff_eq(vec2(0.5, 0.4), 1e-6));
+    }
+
+    #[test]
+    fn pressure_pushes_towards_low_pressure() {
+        let obstacle = Obstacle::new(
+            Shape::Box {
+                half_extents: vec2(0.2, 0.2),
+            },
+            vec2(0.5, 0.5),
+            Motion::Dynamic {
+                mass: 1.0,
+                inertia: 1.0,
+                drag: 0.0,
+            },
+        );
+        // Pressure falling from left to right in fluid at rest
+        let (grid_size_x, grid_size_y) = (8, 8);
+        let velocity = vec![Vec2::ZERO; grid_size_x * grid_size_y];
+        let pressure: Vec<_> = (0..grid_size_x * grid_size_y)
+            .map(|index| (grid_size_x - index % grid_size_x) as f32)
+            .collect();
+
+        let (force, torque) =
+            fluid_force(&obstacle, &velocity, &pressure, grid_size_x, grid_size_y);
+        assert!(force.x > 0.0);
+        assert!(force.y.abs() < 1e-6);
+        assert!(torque.abs() < 1e-6);
+    }
+}
@@ -0,0 +1,179 @@
+use glam::{vec2, Vec2};
+
+use crate::readback::Readback;
+
+// Center, left, right, bottom and top neighbour, in the order their velocities are copied. The
+// density and pressure of the center follow.
+const PROBED_CELLS: usize = 5;
+const VELOCITY_SIZE: u64 = std::mem::size_of::<Vec2>() as u64;
+const SCALAR_SIZE: u64 = std::mem::size_of::<f32>() as u64;
+
+// Field values of a single cell
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProbeValue {
+    pub cell: (usize, usize),
+    pub step: u64,
+    pub velocity: Vec2,
+    pub density: f32,
+    // Same finite differences as the diagnostics, one sided at the walls
+    pub divergence: f32,
+    // Pressure of the last projection, zero in solid cells
+    pub pressure: f32,
+}
+
+// Reads back the cell under the cursor and its neighbours. One read is in flight at a time and the
+// value arrives a frame or two after it was requested.
+pub(crate) struct Probe {
+    // Cell and step of the read in flight
+    readback: Readback<((usize, usize), u64)>,
+    value: Option<ProbeValue>,
+}
+
+impl Probe {
+    pub(crate) fn new(device: &wgpu::Device) -> Self {
+        Self {
+            readback: Readback::new(
+                device,
+                "probe_readback_buffer",
+                PROBED_CELLS as u64 * VELOCITY_SIZE + 2 * SCALAR_SIZE,
+            ),
+            value: None,
+        }
+    }
+
+    // Collects the pending read if it has arrived, then starts a new one for the cell if none is
+    // in flight. Returns the most recent value of the cell.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn read(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        velocity_buffer: &wgpu::Buffer,
+        density_buffer: &wgpu::Buffer,
+        pressure_buffer: &wgpu::Buffer,
+        grid_size: (usize, usize),
+        cell: (usize, usize),
+        step: u64,
+    ) -> Option<ProbeValue> {
+        device.poll(wgpu::Maintain::Poll);
+        if let Some(value) = self
+            .readback
+            .poll(|(cell, step), data| value(bytemuck::cast_slice(data), grid_size, cell, step))
+        {
+            self.value = Some(value);
+        }
+
+        let (grid_size_x, grid_size_y) = grid_size;
+        let (x, y) = cell;
+        let recorded = self.readback.try_record(|buffer| {
+            let neighbours = [
+                (x, y),
+                (x.saturating_sub(1), y),
+                ((x + 1).min(grid_size_x - 1), y),
+                (x, y.saturating_sub(1)),
+                (x, (y + 1).min(grid_size_y - 1)),
+            ];
+
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("probe_readback_encoder"),
+            });
+            for (index, (x, y)) in neighbours.iter().enumerate() {
+                encoder.copy_buffer_to_buffer(
+                    velocity_buffer,
+                    (x + y * grid_size_x) as u64 * VELOCITY_SIZE,
+                    buffer,
+                    index as u64 * VELOCITY_SIZE,
+                    VELOCITY_SIZE,
+                );
+            }
+            for (index, scalar_buffer) in [density_buffer, pressure_buffer].iter().enumerate() {
+                encoder.copy_buffer_to_buffer(
+                    scalar_buffer,
+                    (x + y * grid_size_x) as u64 * SCALAR_SIZE,
+                    buffer,
+                    PROBED_CELLS as u64 * VELOCITY_SIZE + index as u64 * SCALAR_SIZE,
+                    SCALAR_SIZE,
+                );
+            }
+            queue.submit(Some(encoder.finish()));
+            (cell, step)
+        });
+        if recorded {
+            // Starts mapping the copy that was just submitted
+            self.readback.poll(|_, _| ());
+        }
+
+        self.value.filter(|value| value.cell == cell)
+    }
+}
+
+fn value(data: &[f32], grid_size: (usize, usize), cell: (usize, usize), step: u64) -> ProbeValue {
+    let velocity = |index: usize| vec2(data[2 * index], data[2 * index + 1]);
+    let (grid_size_x, grid_size_y) = grid_size;
+    let (x, y) = cell;
+
+    // Distance between the neighbours in grid space, a single cell at the walls
+    let distance = |position: usize, size: usize| {
+        let low = position.saturating_sub(1);
+        let high = (position + 1).min(size - 1);
+        (high - low).max(1) as f32 / size as f32
+    };
+    let divergence = (velocity(2).x - velocity(1).x) / distance(x, grid_size_x)
+        + (velocity(4).y - velocity(3).y) / distance(y, grid_size_y);
+
+    ProbeValue {
+        cell,
+        step,
+        velocity: velocity(0),
+        density: data[2 * PROBED_CELLS],
+        divergence,
+        pressure: data[2 * PROBED_CELLS + 1],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Copied data of the center and its neighbours, with a density of 0.5 and a pressure of 2
+    fn data(velocities: [Vec2; PROBED_CELLS]) -> Vec<f32> {
+        velocities
+            .iter()
+            .flat_map(|velocity| velocity.to_array())
+            .chain([0.5, 2.0])
+            .collect()
+    }
+
+    #[test]
+    fn central_differences_inside() {
+        let data = data([
+            vec2(2.0, 1.0),
+            vec2(1.0, 0.0),
+            vec2(3.0, 0.0),
+            vec2(0.0, 0.0),
+            vec2(0.0, 2.0),
+        ]);
+        let value = value(&data, (4, 4), (1, 1), 7);
+        // Neighbours half the domain apart on a 4x4 grid
+        assert_eq!(value.divergence, 8.0);
+        assert_eq!(value.velocity, vec2(2.0, 1.0));
+        assert_eq!(value.density, 0.5);
+        assert_eq!(value.pressure, 2.0);
+        assert_eq!(value.step, 7);
+    }
+
+    #[test]
+    fn one_sided_differences_at_the_walls() {
+        // The missing left and top neighbours are the center itself
+        let data = data([
+            vec2(1.0, 1.0),
+            vec2(1.0, 1.0),
+            vec2(2.0, 0.0),
+            vec2(0.0, 0.0),
+            vec2(1.0, 1.0),
+        ]);
+        let value = value(&data, (4, 4), (0, 3), 0);
+        // A quarter of the domain between the center and the neighbour on the other side
+        assert_eq!(value.divergence, 8.0);
+    }
+}
@@ -0,0 +1,160 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use crate::readback::Readback;
+
+// Number of frames kept for the table and the graph
+pub const HISTORY_LENGTH: usize = 300;
+// Frames that can be waiting for their timestamps at the same time. Frames are not timed while
+// all are busy.
+const FRAME_SLOT_COUNT: usize = 4;
+// Timed passes per frame, passes after these are not timed
+const MAX_PASSES: usize = 8;
+const QUERIES_PER_SLOT: usize = 2 * MAX_PASSES;
+
+// GPU time of one pass in milliseconds
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PassTiming {
+    pub name: &'static str,
+    pub milliseconds: f32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FrameTimings {
+    pub passes: Vec<PassTiming>,
+}
+
+impl FrameTimings {
+    pub fn total_milliseconds(&self) -> f32 {
+        self.passes.iter().map(|pass| pass.milliseconds).sum()
+    }
+}
+
+// Times passes with timestamp queries written before and after them. Each frame records into one
+// of a few slots, whose timestamps are resolved and mapped without waiting once the frame has been
+// submitted.
+pub(crate) struct GpuProfiler {
+    query_set: wgpu::QuerySet,
+    // Nanoseconds per timestamp tick
+    timestamp_period: f32,
+    // Names of the passes timed in each slot
+    slots: Vec<Readback<Vec<&'static str>>>,
+    current_slot: Option<usize>,
+    // Passes timed so far in the current slot
+    passes: Mutex<Vec<&'static str>>,
+    history: VecDeque<FrameTimings>,
+}
+
+impl GpuProfiler {
+    // None if the device was created without timestamp queries
+    pub(crate) fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("gpu_profiler_query_set"),
+            ty: wgpu::QueryType::Timestamp,
+            count: (FRAME_SLOT_COUNT * QUERIES_PER_SLOT) as u32,
+        });
+        let slot_size = (QUERIES_PER_SLOT * wgpu::QUERY_SIZE as usize) as u64;
+        let slots = (0..FRAME_SLOT_COUNT)
+            .map(|_| Readback::new(device, "gpu_profiler_readback_buffer", slot_size))
+            .collect();
+
+        Some(Self {
+            query_set,
+            timestamp_period: queue.get_timestamp_period(),
+            slots,
+            current_slot: None,
+            passes: Mutex::new(Vec::new()),
+            history: VecDeque::with_capacity(HISTORY_LENGTH),
+        })
+    }
+
+    // Picks a free slot for the passes of the coming frame
+    pub(crate) fn begin_frame(&mut self) {
+        self.current_slot = self.slots.iter().position(Readback::is_free);
+        self.passes.get_mut().unwrap().clear();
+    }
+
+    // Writes the timestamp before a pass. Returns the query index to pass to end_pass, or None if
+    // the pass is not timed.
+    pub(crate) fn begin_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        name: &'static str,
+    ) -> Option<u32> {
+        let slot_index = self.current_slot?;
+        let mut passes = self.passes.lock().unwrap();
+        if passes.len() == MAX_PASSES {
+            return None;
+        }
+
+        let query = (slot_index * QUERIES_PER_SLOT + 2 * passes.len()) as u32;
+        passes.push(name);
+        encoder.write_timestamp(&self.query_set, query);
+        Some(query)
+    }
+
+    pub(crate) fn end_pass(&self, encoder: &mut wgpu::CommandEncoder, query: Option<u32>) {
+        if let Some(query) = query {
+            encoder.write_timestamp(&self.query_set, query + 1);
+        }
+    }
+
+    // Resolves the timestamps of the frame and collects the frames whose timestamps have arrived.
+    // Must be called after the frame has been submitted.
+    pub(crate) fn end_frame(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let passes = std::mem::take(self.passes.get_mut().unwrap());
+        if let Some(slot_index) = self.current_slot.take().filter(|_| !passes.is_empty()) {
+            self.slots[slot_index].try_record(|buffer| {
+                let first_query = (slot_index * QUERIES_PER_SLOT) as u32;
+                let query_count = 2 * passes.len() as u32;
+
+                let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("gpu_profiler_resolve_encoder"),
+                });
+                encoder.resolve_query_set(
+                    &self.query_set,
+                    first_query..first_query + query_count,
+                    buffer,
+                    0,
+                );
+                queue.submit(Some(encoder.finish()));
+                passes
+            });
+        }
+
+        device.poll(wgpu::Maintain::Poll);
+        for slot in &self.slots {
+            let timestamp_period = self.timestamp_period;
+            let timings = slot.poll(|passes, data| {
+                // Only the timestamps of the timed passes were resolved
+                let timestamps: &[u64] = bytemuck::cast_slice(data);
+                FrameTimings {
+                    passes: passes
+                        .iter()
+                        .zip(timestamps.chunks_exact(2))
+                        .map(|(name, timestamps)| PassTiming {
+                            name,
+                            milliseconds: timestamps[1].saturating_sub(timestamps[0]) as f32
+                                * timestamp_period
+                                / 1e6,
+                        })
+                        .collect(),
+                }
+            });
+            if let Some(timings) = timings {
+                if self.history.len() == HISTORY_LENGTH {
+                    self.history.pop_front();
+                }
+                self.history.push_back(timings);
+            }
+        }
+    }
+
+    // Oldest frame first. Frames can be missing when the readbacks fall behind.
+    pub(crate) fn history(&self) -> &VecDeque<FrameTimings> {
+        &self.history
+    }
+}
@@ -0,0 +1,83 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll},
+};
+
+use futures::FutureExt;
+
+type Mapping = Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>> + Send>>;
+
+enum State<T> {
+    Free,
+    // The copy into the buffer is recorded but maybe not submitted yet
+    Recorded(T),
+    Mapping(T, Mapping),
+}
+
+// Buffer that GPU data is copied into and mapped without waiting. One copy is in flight at a time;
+// its data arrives a frame or two after it was submitted, together with the value that was
+// returned when recording it.
+pub(crate) struct Readback<T> {
+    buffer: wgpu::Buffer,
+    state: Mutex<State<T>>,
+}
+
+impl<T> Readback<T> {
+    pub(crate) fn new(device: &wgpu::Device, label: &str, size: u64) -> Self {
+        Self {
+            buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                size,
+                mapped_at_creation: false,
+            }),
+            state: Mutex::new(State::Free),
+        }
+    }
+
+    pub(crate) fn is_free(&self) -> bool {
+        matches!(*self.state.lock().unwrap(), State::Free)
+    }
+
+    // Calls record with the buffer to copy into if no copy is in flight. Returns whether it was
+    // called.
+    pub(crate) fn try_record(&self, record: impl FnOnce(&wgpu::Buffer) -> T) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if !matches!(*state, State::Free) {
+            return false;
+        }
+        *state = State::Recorded(record(&self.buffer));
+        true
+    }
+
+    // Starts mapping a recorded copy, which must have been submitted by now, or reads a finished
+    // one with read. Returns what read returned, if it was called. The device has to be polled
+    // before for the mapping to make progress.
+    pub(crate) fn poll<R>(&self, read: impl FnOnce(T, &[u8]) -> R) -> Option<R> {
+        let mut state = self.state.lock().unwrap();
+        let mut result = None;
+        *state = match std::mem::replace(&mut *state, State::Free) {
+            State::Free => State::Free,
+            State::Recorded(value) => State::Mapping(
+                value,
+                Box::pin(self.buffer.slice(..).map_async(wgpu::MapMode::Read)),
+            ),
+            State::Mapping(value, mut mapping) => {
+                let mut context = Context::from_waker(futures::task::noop_waker_ref());
+                match mapping.poll_unpin(&mut context) {
+                    Poll::Pending => State::Mapping(value, mapping),
+                    Poll::Ready(mapped) => {
+                        if mapped.is_ok() {
+                            result = Some(read(value, &self.buffer.slice(..).get_mapped_range()));
+                        }
+                        self.buffer.unmap();
+                        State::Free
+                    }
+                }
+            }
+        };
+        result
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::Arc;
+
+use crate::fluid_simulator::{FluidSimulator, Visualization};
+
+// rend3 integration, a thin layer over the CommandEncoder recording of FluidSimulator
+impl FluidSimulator {
+    pub fn new(
+        renderer: &rend3::Renderer,
+        surface_format: wgpu::TextureFormat,
+        grid_size_x: usize,
+        grid_size_y: usize,
+    ) -> Self {
+        Self::with_device(
+            Arc::clone(&renderer.device),
+            Arc::clone(&renderer.queue),
+            surface_format,
+            grid_size_x,
+            grid_size_y,
+        )
+    }
+
+    pub fn add_forces_in_field_to_graph<'node>(&'node self, graph: &mut rend3::RenderGraph<'node>) {
+        let mut builder = graph.add_node("fluid_simulator_add_forces_and_density");
+
+        let _data_output = builder.add_data_output::<_, wgpu::Buffer>("Fluid Fields");
+
+        builder.build(
+            move |_pt, _renderer, encoder_or_pass, _temps, _ready, _graph_data| {
+                let encoder = encoder_or_pass.get_encoder();
+
+                self.record_step(encoder);
+
+                //graph_data.set_data(data_output, Some(&self.velocity_buffer));
+            },
+        );
+    }
+
+    fn add_visualization_to_graph<'node>(
+        &'node self,
+        graph: &mut rend3::RenderGraph<'node>,
+        name: &str,
+        visualization: Visualization,
+    ) {
+        let mut builder = graph.add_node(name);
+
+        let output_handle = builder.add_surface_output();
+        let _data_input_handle = builder.add_data_input::<_, wgpu::Buffer>("Fluid Fields");
+
+        builder.build(
+            move |_pt, _renderer, encoder_or_pass, _temps, _ready, graph_data| {
+                let encoder = encoder_or_pass.get_encoder();
+
+                let output = graph_data.get_render_target(output_handle);
+
+                self.record_visualization(encoder, visualization, output);
+            },
+        );
+    }
+
+    pub fn add_velocity_visualization_to_graph<'node>(
+        &'node self,
+        graph: &mut rend3::RenderGraph<'node>,
+    ) {
+        self.add_visualization_to_graph(graph, "velocity_field_visualize", Visualization::Velocity);
+    }
+
+    pub fn add_density_visualization_to_graph<'node>(
+        &'node self,
+        graph: &mut rend3::RenderGraph<'node>,
+    ) {
+        self.add_visualization_to_graph(graph, "density_field_visualize", Visualization::Density);
+    }
+}
@@ -0,0 +1,156 @@
+use glam::{vec2, Vec2};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    fluid_simulator::{FluidSimulator, ForceField},
+    obstacles::{Motion, Obstacle, Shape},
+    scene::Parameters,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scenario {
+    Empty,
+    LidDrivenCavity,
+    KarmanVortexStreet,
+    KelvinHelmholtz,
+    RayleighTaylor,
+}
+
+impl Scenario {
+    pub const ALL: [Scenario; 5] = [
+        Scenario::Empty,
+        Scenario::LidDrivenCavity,
+        Scenario::KarmanVortexStreet,
+        Scenario::KelvinHelmholtz,
+        Scenario::RayleighTaylor,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scenario::Empty => "Empty",
+            Scenario::LidDrivenCavity => "Lid-Driven Cavity",
+            Scenario::KarmanVortexStreet => "Karman Vortex Street",
+            Scenario::KelvinHelmholtz => "Kelvin-Helmholtz",
+            Scenario::RayleighTaylor => "Rayleigh-Taylor",
+        }
+    }
+
+    // Every parameter of the scenario, the defaults where it does not set one
+    pub fn parameters(&self) -> Parameters {
+        let defaults = Parameters::default();
+        match self {
+            Scenario::Empty => defaults,
+            Scenario::LidDrivenCavity => Parameters {
+                velocity_dissipation: 0.1,
+                density_dissipation: 0.0,
+                ..defaults
+            },
+            Scenario::KarmanVortexStreet => Parameters {
+                velocity_dissipation: 0.1,
+                density_dissipation: 0.1,
+                ..defaults
+            },
+            Scenario::KelvinHelmholtz => Parameters {
+                velocity_dissipation: 0.0,
+                density_dissipation: 0.0,
+                ..defaults
+            },
+            Scenario::RayleighTaylor => Parameters {
+                velocity_dissipation: 0.0,
+                density_dissipation: 0.0,
+                gravity: vec2(0.0, -1.0),
+                ..defaults
+            },
+        }
+    }
+
+    // Resets the simulator parameters and fields to the initial state of the scenario
+    pub fn apply(&self, simulator: &mut FluidSimulator) {
+        simulator.set_parameters(&self.parameters());
+        simulator.force_fields.clear();
+        simulator.obstacles.clear();
+
+        match self {
+            Scenario::Empty | Scenario::KelvinHelmholtz | Scenario::RayleighTaylor => {}
+            Scenario::LidDrivenCavity => {
+                // The lid is a thin band along the top wall dragging the fluid to the right
+                simulator.force_fields.push(ForceField::Wind {
+                    min: vec2(0.0, 0.95),
+                    max: vec2(1.0, 1.0),
+                    force: vec2(5.0, 0.0),
+                });
+            }
+            Scenario::KarmanVortexStreet => {
+                // Inflow on the left side of the domain
+                simulator.force_fields.push(ForceField::Wind {
+                    min: vec2(0.0, 0.0),
+                    max: vec2(0.05, 1.0),
+                    force: vec2(2.0, 0.0),
+                });
+                simulator.obstacles.push(Obstacle::new(
+                    Shape::Circle { radius: 0.08 },
+                    vec2(0.25, 0.5),
+                    Motion::Static,
+                ));
+            }
+        }
+
+        let (grid_size_x, grid_size_y) = simulator.grid_size();
+        let (velocity, density) = self.initial_fields(grid_size_x, grid_size_y);
+        simulator.set_initial_conditions(velocity, density);
+    }
+
+    // Row major velocity and density of every cell, starting from the bottom left cell
+    pub fn initial_fields(&self, grid_size_x: usize, grid_size_y: usize) -> (Vec<Vec2>, Vec<f32>) {
+        let mut velocity = Vec::with_capacity(grid_size_x * grid_size_y);
+        let mut density = Vec::with_capacity(grid_size_x * grid_size_y);
+        for y in 0..grid_size_y {
+            for x in 0..grid_size_x {
+                let position =
+                    (vec2(x as f32, y as f32) + 0.5) / vec2(grid_size_x as f32, grid_size_y as f32);
+                let (cell_velocity, cell_density) = self.initial_cell(position);
+                velocity.push(cell_velocity);
+                density.push(cell_density);
+            }
+        }
+        (velocity, density)
+    }
+
+    // Velocity and density at a position in grid space, from (0, 0) to (1, 1)
+    fn initial_cell(&self, position: Vec2) -> (Vec2, f32) {
+        use std::f32::consts::PI;
+
+        match self {
+            Scenario::Empty => (Vec2::ZERO, 0.0),
+            Scenario::LidDrivenCavity => {
+                let density = if position.y < 0.5 { 1.0 } else { 0.0 };
+                (Vec2::ZERO, density)
+            }
+            Scenario::KarmanVortexStreet => {
+                // Horizontal dye streaks carried by a uniform stream
+                let density = if (position.y * 10.0) as u32 & 1 == 0 {
+                    1.0
+                } else {
+                    0.0
+                };
+                (vec2(1.0, 0.0), density)
+            }
+            Scenario::KelvinHelmholtz => {
+                // Two opposite streams with a small sinusoidal perturbation of the shear layer
+                let stream = if position.y > 0.5 { 1.0 } else { -1.0 };
+                let distance_to_layer = (position.y - 0.5).abs();
+                let perturbation =
+                    0.05 * (4.0 * 2.0 * PI * position.x).sin() * (-distance_to_layer * 20.0).exp();
+                let density = if position.y > 0.5 { 1.0 } else { 0.0 };
+                (vec2(stream, perturbation), density)
+            }
+            Scenario::RayleighTaylor => {
+                // Heavy fluid on top of light fluid, with a perturbed interface. The solver does not
+                // couple density into the momentum yet, so this only sets the initial conditions.
+                let interface = 0.5 + 0.02 * (2.0 * PI * position.x).cos();
+                let density = if position.y > interface { 1.0 } else { 0.0 };
+                (Vec2::ZERO, density)
+            }
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    fluid_simulator::{
+        self, FluidSimulator, ForceField, Visualization, DEFAULT_GRID_SIZE_X, DEFAULT_GRID_SIZE_Y,
+        MAX_GRID_SIZE,
+    },
+    image_import::{self, Channel},
+    obstacles::{Motion, Obstacle, Shape},
+    scenarios::Scenario,
+};
+
+// Declarative description of an experiment, stored as RON or as TOML depending on the file
+// extension. Everything that is left out gets the same default as a new FluidSimulator. Paths are
+// relative to the scene file.
+//
+// The simulator has a single solver and the domain is always a closed box with free-slip walls, so
+// there are no entries for them. The forced density is the only dye emitter and the force fields are the
+// momentum emitters.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Scene {
+    pub grid_size: [usize; 2],
+    pub visualization: Visualization,
+    pub parameters: Parameters,
+    pub initial_fields: InitialFields,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub force_fields: Vec<ForceField>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub obstacles: Vec<SceneObstacle>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Parameters {
+    pub time_step: f32,
+    pub forced_velocity: Vec2,
+    pub forced_density: f32,
+    pub velocity_dissipation: f32,
+    pub density_dissipation: f32,
+    pub gravity: Vec2,
+}
+
+// The fields start at zero and each source that is set overrides them in this order: scenario,
+// .npy files, density image, inline values
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InitialFields {
+    // Only the fields of the scenario are used, its parameters and obstacles are not
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scenario: Option<Scenario>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub velocity_npy: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub density_npy: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub density_image: Option<PathBuf>,
+    pub density_image_channel: Channel,
+    // Row major, starting from the bottom left cell
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub velocity: Vec<Vec2>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub density: Vec<f32>,
+}
+
+// Obstacle at rest. Scripted motions start from the position.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SceneObstacle {
+    pub position: Vec2,
+    #[serde(default)]
+    pub rotation: f32,
+    pub shape: Shape,
+    #[serde(default = "static_motion")]
+    pub motion: Motion,
+}
+
+fn static_motion() -> Motion {
+    Motion::Static
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self {
+            grid_size: [DEFAULT_GRID_SIZE_X, DEFAULT_GRID_SIZE_Y],
+            visualization: Visualization::Density,
+            parameters: Parameters::default(),
+            initial_fields: InitialFields::default(),
+            force_fields: Vec::new(),
+            obstacles: Vec::new(),
+        }
+    }
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Self {
+            time_step: 1.0 / 60.0,
+            forced_velocity: Vec2::ZERO,
+            forced_density: 0.0,
+            velocity_dissipation: 1.0,
+            density_dissipation: 1.0,
+            gravity: Vec2::ZERO,
+        }
+    }
+}
+
+fn invalid_data(message: impl ToString) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "toml")
+}
+
+impl Scene {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        Self::parse(&fs::read_to_string(path)?, is_toml(path))
+    }
+
+    fn parse(text: &str, is_toml: bool) -> io::Result<Self> {
+        let scene: Self = if is_toml {
+            toml::from_str(text).map_err(invalid_data)?
+        } else {
+            ron::from_str(text).map_err(invalid_data)?
+        };
+        scene.validate()?;
+        Ok(scene)
+    }
+
+    // Checks the values a simulator can not be built from, which would otherwise fail on the GPU
+    // or when sampling the obstacles
+    pub fn validate(&self) -> io::Result<()> {
+        let [grid_size_x, grid_size_y] = self.grid_size;
+        if !(1..=MAX_GRID_SIZE).contains(&grid_size_x)
+            || !(1..=MAX_GRID_SIZE).contains(&grid_size_y)
+        {
+            return Err(invalid_data(format!(
+                "scene grid size {}x{} is outside 1x1 to {}x{}",
+                grid_size_x, grid_size_y, MAX_GRID_SIZE, MAX_GRID_SIZE
+            )));
+        }
+        for (index, obstacle) in self.obstacles.iter().enumerate() {
+            obstacle
+                .shape
+                .validate()
+                .and_then(|()| obstacle.motion.validate())
+                .map_err(|error| invalid_data(format!("obstacle {}: {}", index, error)))?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let text = if is_toml(path) {
+            toml::to_string_pretty(self).map_err(invalid_data)?
+        } else {
+            ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+                .map_err(invalid_data)?
+        };
+        fs::write(path, text)
+    }
+
+    // Captures the current parameters, force fields and obstacles, with the initial fields stored
+    // inline so the scene does not depend on any other file
+    pub fn from_simulator(simulator: &FluidSimulator, visualization: Visualization) -> Self {
+        let (grid_size_x, grid_size_y) = simulator.grid_size();
+        let (velocity, density) = simulator.initial_fields();
+        Self {
+            grid_size: [grid_size_x, grid_size_y],
+            visualization,
+            parameters: simulator.parameters(),
+            initial_fields: InitialFields {
+                velocity: velocity.to_vec(),
+                density: density.to_vec(),
+                ..InitialFields::default()
+            },
+            force_fields: simulator.force_fields.clone(),
+            obstacles: simulator
+                .obstacles
+                .iter()
+                .map(|obstacle| SceneObstacle {
+                    position: obstacle.origin,
+                    rotation: obstacle.initial_rotation,
+                    shape: obstacle.shape.clone(),
+                    motion: obstacle.motion,
+                })
+                .collect(),
+        }
+    }
+
+    // Creates a simulator with the grid size of the scene and applies the scene to it
+    pub fn build(
+        &self,
+        device: &Arc<wgpu::Device>,
+        queue: &Arc<wgpu::Queue>,
+        surface_format: wgpu::TextureFormat,
+        directory: &Path,
+    ) -> io::Result<FluidSimulator> {
+        self.validate()?;
+        let mut simulator = FluidSimulator::with_device(
+            Arc::clone(device),
+            Arc::clone(queue),
+            surface_format,
+            self.grid_size[0],
+            self.grid_size[1],
+        );
+        self.apply(&mut simulator, directory)?;
+        Ok(simulator)
+    }
+
+    // Configures a simulator that already has the grid size of the scene. Relative paths are
+    // resolved against the directory. The simulator is left untouched on failure.
+    pub fn apply(&self, simulator: &mut FluidSimulator, directory: &Path) -> io::Result<()> {
+        self.validate()?;
+        let (grid_size_x, grid_size_y) = simulator.grid_size();
+        if self.grid_size != [grid_size_x, grid_size_y] {
+            return Err(invalid_data(format!(
+                "scene grid size is {}x{}, the simulator has {}x{}",
+                self.grid_size[0], self.grid_size[1], grid_size_x, grid_size_y
+            )));
+        }
+        let (velocity, density) = self
+            .initial_fields
+            .build(grid_size_x, grid_size_y, directory)?;
+
+        simulator.set_parameters(&self.parameters);
+        simulator.force_fields = self.force_fields.clone();
+        simulator.obstacles = self.build_obstacles();
+        simulator.set_initial_conditions(velocity, density);
+        Ok(())
+    }
+
+    // Obstacles at rest at their scene positions
+    pub fn build_obstacles(&self) -> Vec<Obstacle> {
+        self.obstacles
+            .iter()
+            .map(|obstacle| {
+                let mut built =
+                    Obstacle::new(obstacle.shape.clone(), obstacle.position, obstacle.motion);
+                built.initial_rotation = obstacle.rotation;
+                built.rotation = obstacle.rotation;
+                built
+            })
+            .collect()
+    }
+}
+
+impl InitialFields {
+    // Row major velocity and density for the grid size. Relative paths are resolved against the
+    // directory.
+    pub fn build(
+        &self,
+        grid_size_x: usize,
+        grid_size_y: usize,
+        directory: &Path,
+    ) -> io::Result<(Vec<Vec2>, Vec<f32>)> {
+        let cell_count = grid_size_x * grid_size_y;
+        let (mut velocity, mut density) = match self.scenario {
+            Some(scenario) => scenario.initial_fields(grid_size_x, grid_size_y),
+            None => (vec![Vec2::ZERO; cell_count], vec![0.0; cell_count]),
+        };
+
+        if let Some(path) = &self.velocity_npy {
+            velocity = fluid_simulator::read_npy_field(
+                &directory.join(path),
+                &[grid_size_y, grid_size_x, 2],
+            )?
+            .chunks_exact(2)
+            .map(|velocity| Vec2::new(velocity[0], velocity[1]))
+            .collect();
+        }
+        if let Some(path) = &self.density_npy {
+            density = fluid_simulator::read_npy_field(
+                &directory.join(path),
+                &[grid_size_y, grid_size_x],
+            )?;
+        }
+        if let Some(path) = &self.density_image {
+            density = image_import::read_png(directory.join(path))?.resample(
+                self.density_image_channel,
+                grid_size_x,
+                grid_size_y,
+            );
+        }
+
+        if !self.velocity.is_empty() {
+            if self.velocity.len() != cell_count {
+                return Err(invalid_data(format!(
+                    "scene has {} initial velocities, expected {}",
+                    self.velocity.len(),
+                    cell_count
+                )));
+            }
+            velocity = self.velocity.clone();
+        }
+        if !self.density.is_empty() {
+            if self.density.len() != cell_count {
+                return Err(invalid_data(format!(
+                    "scene has {} initial densities, expected {}",
+                    self.density.len(),
+                    cell_count
+                )));
+            }
+            density = self.density.clone();
+        }
+
+        Ok((velocity, density))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ron_and_toml() {
+        let scene = Scene::parse("(grid_size: (40, 20))", false).unwrap();
+        assert_eq!(scene.grid_size, [40, 20]);
+
+        let scene = Scene::parse("grid_size = [8, 16]\n", true).unwrap();
+        assert_eq!(scene.grid_size, [8, 16]);
+        assert_eq!(scene.parameters, Parameters::default());
+    }
+
+    #[test]
+    fn rejects_invalid_grid_size() {
+        for text in ["(grid_size: (0, 0))", "(grid_size: (20, 100000))"] {
+            let error = Scene::parse(text, false).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn rejects_mask_of_wrong_size() {
+        let text = r#"(
+            obstacles: [(
+                position: (0.5, 0.5),
+                shape: (type: "Mask", half_extents: (0.1, 0.1), width: 2, height: 2, solid: [true]),
+            )],
+        )"#;
+        let error = Scene::parse(text, false).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        let text = text.replace("width: 2, height: 2", "width: 0, height: 0");
+        let error = Scene::parse(&text.replace("[true]", "[]"), false).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_dynamic_obstacle_without_mass() {
+        let text = r#"(
+            obstacles: [(
+                position: (0.5, 0.5),
+                shape: (type: "Circle", radius: 0.1),
+                motion: (type: "Dynamic", mass: 0.0, inertia: 0.001, drag: 1.0),
+            )],
+        )"#;
+        let error = Scene::parse(text, false).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        let text = text.replace("mass: 0.0, inertia: 0.001", "mass: 0.1, inertia: -1.0");
+        let error = Scene::parse(&text, false).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        let text = text.replace("inertia: -1.0", "inertia: 0.001");
+        assert!(Scene::parse(&text, false).is_ok());
+    }
+}
@@ -0,0 +1,64 @@
+// SPIR-V of the HLSL entry points. build.rs compiles the entry points into OUT_DIR and spirv!
+// embeds them. With the runtime-shaders feature spirv! compiles the HLSL with DXC when the
+// simulator is created instead, which needs the DXC shared library at runtime.
+
+// Every entry point used here has to be listed in SHADERS in build.rs
+#[cfg(not(feature = "runtime-shaders"))]
+macro_rules! spirv {
+    ($file:literal, $entry_point:literal, $profile:literal) => {
+        include_bytes!(concat!(
+            env!("OUT_DIR"),
+            "/",
+            $file,
+            ".",
+            $entry_point,
+            ".spv"
+        ))
+        .to_vec()
+    };
+}
+
+#[cfg(feature = "runtime-shaders")]
+macro_rules! spirv {
+    ($file:literal, $entry_point:literal, $profile:literal) => {
+        $crate::shaders::compile(
+            concat!("src/shaders/", $file, ".hlsl"),
+            include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/src/shaders/",
+                $file,
+                ".hlsl"
+            )),
+            $entry_point,
+            $profile,
+        )
+    };
+}
+
+pub(crate) use spirv;
+
+#[cfg(feature = "runtime-shaders")]
+pub(crate) fn compile(path: &str, source: &str, entry_point: &str, profile: &str) -> Vec<u8> {
+    let dxc = hassle_rs::Dxc::new().expect("Failed to load the DXC shared library");
+    let compiler = dxc.create_compiler().unwrap();
+    let library = dxc.create_library().unwrap();
+    let blob = library.create_blob_with_encoding_from_str(source).unwrap();
+
+    let result = match compiler.compile(&blob, path, entry_point, profile, &["-spirv"], None, &[]) {
+        Ok(result) => result,
+        Err(result) => {
+            let error_blob = result
+                .0
+                .get_error_buffer()
+                .map_err(hassle_rs::utils::HassleError::Win32Error)
+                .unwrap();
+            panic!(
+                "Failed to compile {} {}:\n{}",
+                path,
+                entry_point,
+                library.get_blob_as_string(&error_blob)
+            );
+        }
+    };
+    result.get_result().unwrap().to_vec()
+}
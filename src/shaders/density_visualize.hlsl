@@ -18,6 +18,13 @@ ConstantBuffer<ConstantsData> g_constant_data : register(b0);
 StructuredBuffer<float2> g_velocity_field : register(t1);
 StructuredBuffer<float> g_density_field : register(t2);
 
+struct ObstacleCellData {
+    float2 velocity;
+    uint solid;
+    uint padding;
+};
+StructuredBuffer<ObstacleCellData> g_obstacles : register(t3);
+
 VSOutput vs_main(uint vertexID : SV_VertexID) {
     VSOutput output;
     output.uv = float2((vertexID << 1) & 2, vertexID & 2);
@@ -27,6 +34,11 @@ VSOutput vs_main(uint vertexID : SV_VertexID) {
 }
 
 float4 ps_main(VSOutput input) : SV_Target0 {
-    uint2 position_in_grid = input.uv * g_constant_data.grid_size;
-    return float4(g_density_field[position_in_grid.x + position_in_grid.y * g_constant_data.grid_size.x], 0.0, 0.0, 1.0);
+    // Row 0 of the grid is at the bottom of the screen, same as in the velocity visualization
+    uint2 position_in_grid = float2(input.uv.x, 1.0 - input.uv.y) * g_constant_data.grid_size;
+    const uint index = position_in_grid.x + position_in_grid.y * g_constant_data.grid_size.x;
+    if(g_obstacles[index].solid != 0) {
+        return float4(0.5, 0.5, 0.5, 1.0);
+    }
+    return float4(g_density_field[index], 0.0, 0.0, 1.0);
 }
@@ -0,0 +1,124 @@
+struct PushConstantData {
+    uint2 grid_size;
+    uint partial_count;
+    // Groups per row of cs_reduce_cells
+    uint group_count_x;
+};
+
+[[vk::push_constant]] PushConstantData g_push_data;
+
+struct ObstacleCellData {
+    float2 velocity;
+    uint solid;
+    uint padding;
+};
+
+// Matches the ReductionData struct in diagnostics.rs
+struct ReductionData {
+    float divergence_sum;
+    float divergence_max;
+    float kinetic_energy;
+    float enstrophy;
+    float mass;
+    float speed_max;
+    uint fluid_cells;
+    uint nan_cells;
+};
+
+StructuredBuffer<float2> g_velocity_field : register(t0);
+StructuredBuffer<float> g_density_field : register(t1);
+StructuredBuffer<ObstacleCellData> g_obstacles : register(t2);
+RWStructuredBuffer<ReductionData> g_partials : register(u3);
+RWStructuredBuffer<ReductionData> g_result : register(u4);
+
+#define GROUP_WIDTH 16
+#define GROUP_SIZE (GROUP_WIDTH * GROUP_WIDTH)
+
+groupshared ReductionData g_shared[GROUP_SIZE];
+
+ReductionData combine(ReductionData a, ReductionData b) {
+    ReductionData result;
+    result.divergence_sum = a.divergence_sum + b.divergence_sum;
+    result.divergence_max = max(a.divergence_max, b.divergence_max);
+    result.kinetic_energy = a.kinetic_energy + b.kinetic_energy;
+    result.enstrophy = a.enstrophy + b.enstrophy;
+    result.mass = a.mass + b.mass;
+    result.speed_max = max(a.speed_max, b.speed_max);
+    result.fluid_cells = a.fluid_cells + b.fluid_cells;
+    result.nan_cells = a.nan_cells + b.nan_cells;
+    return result;
+}
+
+// Tree reduction of g_shared, leaves the result in g_shared[0]
+void reduce_group(uint thread_index) {
+    GroupMemoryBarrierWithGroupSync();
+    for(uint stride = GROUP_SIZE / 2; stride > 0; stride /= 2) {
+        if(thread_index < stride) {
+            g_shared[thread_index] = combine(g_shared[thread_index], g_shared[thread_index + stride]);
+        }
+        GroupMemoryBarrierWithGroupSync();
+    }
+}
+
+float2 velocity_at(uint2 position) {
+    return g_velocity_field[position.x + position.y * g_push_data.grid_size.x];
+}
+
+// Reduces the cells of each square group of cells into one partial result
+[numthreads(GROUP_WIDTH, GROUP_WIDTH, 1)]
+void cs_reduce_cells(uint3 tid : SV_DispatchThreadID, uint group_index : SV_GroupIndex, uint3 group_id : SV_GroupID) {
+    const uint2 grid_size = g_push_data.grid_size;
+    const uint2 position = tid.xy;
+    const uint index = position.x + position.y * grid_size.x;
+    ReductionData cell = (ReductionData)0;
+
+    if(all(position < grid_size) && g_obstacles[index].solid == 0) {
+        const float2 spacing = 1.0 / float2(grid_size);
+
+        // Central differences inside the domain, one sided at the walls
+        const uint2 low = position - min(position, 1);
+        const uint2 high = min(position + 1, grid_size - 1);
+        const float2 distance = max(float2(high - low), 1.0) * spacing;
+        const float2 velocity_dx = (velocity_at(uint2(high.x, position.y)) - velocity_at(uint2(low.x, position.y))) / distance.x;
+        const float2 velocity_dy = (velocity_at(uint2(position.x, high.y)) - velocity_at(uint2(position.x, low.y))) / distance.y;
+        const float divergence = abs(velocity_dx.x + velocity_dy.y);
+        const float vorticity = velocity_dx.y - velocity_dy.x;
+
+        const float2 velocity = g_velocity_field[index];
+        const float cell_area = spacing.x * spacing.y;
+        const float speed = length(velocity);
+
+        if(isnan(speed) || isnan(divergence) || isnan(g_density_field[index])) {
+            cell.nan_cells = 1;
+        } else {
+            cell.divergence_sum = divergence;
+            cell.divergence_max = divergence;
+            cell.kinetic_energy = 0.5 * dot(velocity, velocity) * cell_area;
+            cell.enstrophy = 0.5 * vorticity * vorticity * cell_area;
+            cell.mass = g_density_field[index] * cell_area;
+            cell.speed_max = speed;
+        }
+        cell.fluid_cells = 1;
+    }
+
+    g_shared[group_index] = cell;
+    reduce_group(group_index);
+    if(group_index == 0) {
+        g_partials[group_id.x + group_id.y * g_push_data.group_count_x] = g_shared[0];
+    }
+}
+
+// Reduces the partial results of cs_reduce_cells with a single group
+[numthreads(GROUP_SIZE, 1, 1)]
+void cs_reduce_partials(uint3 group_tid : SV_GroupThreadID) {
+    ReductionData partial = (ReductionData)0;
+    for(uint i = group_tid.x; i < g_push_data.partial_count; i += GROUP_SIZE) {
+        partial = combine(partial, g_partials[i]);
+    }
+
+    g_shared[group_tid.x] = partial;
+    reduce_group(group_tid.x);
+    if(group_tid.x == 0) {
+        g_result[0] = g_shared[0];
+    }
+}
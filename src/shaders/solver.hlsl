@@ -0,0 +1,170 @@
+struct PushConstantData {
+    float time_step;
+};
+
+[[vk::push_constant]] PushConstantData g_push_data;
+
+
+struct ConstantsData {
+    uint2 grid_size;
+};
+ConstantBuffer<ConstantsData> g_constant_data : register(b0);
+
+struct ObstacleCellData {
+    float2 velocity;
+    uint solid;
+    uint padding;
+};
+
+RWStructuredBuffer<float2> g_velocity_field : register(u1);
+RWStructuredBuffer<float> g_density_field : register(u2);
+StructuredBuffer<ObstacleCellData> g_obstacles : register(t3);
+RWStructuredBuffer<float2> g_advected_velocity : register(u4);
+RWStructuredBuffer<float> g_advected_density : register(u5);
+RWStructuredBuffer<float> g_divergence : register(u6);
+StructuredBuffer<float> g_pressure : register(t7);
+RWStructuredBuffer<float> g_next_pressure : register(u8);
+
+uint cell_index(uint2 position) {
+    return position.x + position.y * g_constant_data.grid_size.x;
+}
+
+bool is_outside(int2 position) {
+    return any(position < 0) || any(position >= int2(g_constant_data.grid_size));
+}
+
+// Cells and weights of a bilinear interpolation between cell centers. Positions are in cells and
+// are clamped to the centers of the outermost cells.
+void bilinear(float2 position, out uint4 indices, out float2 weight) {
+    const uint2 grid_size = g_constant_data.grid_size;
+    const float2 clamped = clamp(position, 0.0, float2(grid_size - 1));
+    const uint2 low = uint2(floor(clamped));
+    const uint2 high = min(low + 1, grid_size - 1);
+    weight = clamped - float2(low);
+    indices = uint4(
+        cell_index(low),
+        cell_index(uint2(high.x, low.y)),
+        cell_index(uint2(low.x, high.y)),
+        cell_index(high));
+}
+
+// The walls of the domain are free-slip, a neighbour outside mirrors the normal velocity of the
+// cell next to the wall. Solid cells hold the velocity of their obstacle.
+float2 neighbour_velocity(int2 position, float2 center) {
+    if(position.x < 0 || position.x >= int(g_constant_data.grid_size.x)) {
+        return float2(-center.x, center.y);
+    }
+    if(position.y < 0 || position.y >= int(g_constant_data.grid_size.y)) {
+        return float2(center.x, -center.y);
+    }
+    return g_velocity_field[cell_index(uint2(position))];
+}
+
+// No pressure gradient across the walls and the obstacle surfaces
+float neighbour_pressure(int2 position, float center) {
+    if(is_outside(position) || g_obstacles[cell_index(uint2(position))].solid != 0) {
+        return center;
+    }
+    return g_pressure[cell_index(uint2(position))];
+}
+
+// Semi-Lagrangian advection of velocity and density into the advected fields. Solid cells keep
+// their values.
+[numthreads(8, 8, 1)]
+void cs_advect(uint3 tid : SV_DispatchThreadID) {
+    if(any(tid.xy >= g_constant_data.grid_size)) {
+        return;
+    }
+    const uint index = cell_index(tid.xy);
+    if(g_obstacles[index].solid != 0) {
+        g_advected_velocity[index] = g_velocity_field[index];
+        g_advected_density[index] = g_density_field[index];
+        return;
+    }
+
+    // Velocities are in grid space per second, one cell is 1 / grid size
+    const float2 source = float2(tid.xy) - g_velocity_field[index] * g_push_data.time_step * float2(g_constant_data.grid_size);
+    uint4 indices;
+    float2 weight;
+    bilinear(source, indices, weight);
+
+    g_advected_velocity[index] = lerp(
+        lerp(g_velocity_field[indices.x], g_velocity_field[indices.y], weight.x),
+        lerp(g_velocity_field[indices.z], g_velocity_field[indices.w], weight.x),
+        weight.y);
+    g_advected_density[index] = lerp(
+        lerp(g_density_field[indices.x], g_density_field[indices.y], weight.x),
+        lerp(g_density_field[indices.z], g_density_field[indices.w], weight.x),
+        weight.y);
+}
+
+// Divergence of the velocity divided by the time step, the right hand side of the pressure
+// equation. Zero in solid cells.
+[numthreads(8, 8, 1)]
+void cs_divergence(uint3 tid : SV_DispatchThreadID) {
+    if(any(tid.xy >= g_constant_data.grid_size)) {
+        return;
+    }
+    const uint index = cell_index(tid.xy);
+    if(g_obstacles[index].solid != 0) {
+        g_divergence[index] = 0.0;
+        return;
+    }
+
+    const int2 position = int2(tid.xy);
+    const float2 center = g_velocity_field[index];
+    const float2 left = neighbour_velocity(position + int2(-1, 0), center);
+    const float2 right = neighbour_velocity(position + int2(1, 0), center);
+    const float2 bottom = neighbour_velocity(position + int2(0, -1), center);
+    const float2 top = neighbour_velocity(position + int2(0, 1), center);
+
+    const float2 grid_size = float2(g_constant_data.grid_size);
+    const float divergence = 0.5 * ((right.x - left.x) * grid_size.x + (top.y - bottom.y) * grid_size.y);
+    g_divergence[index] = divergence / g_push_data.time_step;
+}
+
+// One Jacobi iteration of the pressure equation, from the pressure into the next pressure
+[numthreads(8, 8, 1)]
+void cs_jacobi(uint3 tid : SV_DispatchThreadID) {
+    if(any(tid.xy >= g_constant_data.grid_size)) {
+        return;
+    }
+    const uint index = cell_index(tid.xy);
+    if(g_obstacles[index].solid != 0) {
+        g_next_pressure[index] = 0.0;
+        return;
+    }
+
+    const int2 position = int2(tid.xy);
+    const float center = g_pressure[index];
+    const float left = neighbour_pressure(position + int2(-1, 0), center);
+    const float right = neighbour_pressure(position + int2(1, 0), center);
+    const float bottom = neighbour_pressure(position + int2(0, -1), center);
+    const float top = neighbour_pressure(position + int2(0, 1), center);
+
+    // Inverse of the squared cell size
+    const float2 weight = float2(g_constant_data.grid_size) * float2(g_constant_data.grid_size);
+    g_next_pressure[index] = ((left + right) * weight.x + (bottom + top) * weight.y - g_divergence[index]) / (2.0 * (weight.x + weight.y));
+}
+
+// Subtracts the pressure gradient from the velocity of the fluid cells, leaving it divergence free
+[numthreads(8, 8, 1)]
+void cs_subtract_gradient(uint3 tid : SV_DispatchThreadID) {
+    if(any(tid.xy >= g_constant_data.grid_size)) {
+        return;
+    }
+    const uint index = cell_index(tid.xy);
+    if(g_obstacles[index].solid != 0) {
+        return;
+    }
+
+    const int2 position = int2(tid.xy);
+    const float center = g_pressure[index];
+    const float left = neighbour_pressure(position + int2(-1, 0), center);
+    const float right = neighbour_pressure(position + int2(1, 0), center);
+    const float bottom = neighbour_pressure(position + int2(0, -1), center);
+    const float top = neighbour_pressure(position + int2(0, 1), center);
+
+    const float2 gradient = 0.5 * float2(right - left, top - bottom) * float2(g_constant_data.grid_size);
+    g_velocity_field[index] -= gradient * g_push_data.time_step;
+}
@@ -1,6 +1,11 @@
 struct PushConstantData {
     float2 forced_velocity;
     float forced_density;
+    float time_step;
+    float velocity_dissipation;
+    float density_dissipation;
+    float2 gravity;
+    uint force_field_count;
 };
 
 [[vk::push_constant]] PushConstantData g_push_data;
@@ -11,16 +16,80 @@ struct ConstantsData {
 };
 ConstantBuffer<ConstantsData> g_constant_data : register(b0);
 
+static const uint FORCE_FIELD_KIND_RADIAL = 0;
+static const uint FORCE_FIELD_KIND_VORTEX = 1;
+static const uint FORCE_FIELD_KIND_WIND = 2;
+
+struct ForceFieldData {
+    uint kind;
+    float radius;
+    float strength;
+    float padding;
+    float2 position;
+    float2 extent;
+    float2 force;
+};
+
+struct ObstacleCellData {
+    float2 velocity;
+    uint solid;
+    uint padding;
+};
+
 RWStructuredBuffer<float2> g_velocity_field : register(u1);
 RWStructuredBuffer<float> g_density_field : register(u2);
+StructuredBuffer<ForceFieldData> g_force_fields : register(t3);
+StructuredBuffer<ObstacleCellData> g_obstacles : register(t4);
 
-[numthreads(32, 1, 1)]
+float2 evaluate_force_field(ForceFieldData field, float2 position) {
+    if(field.kind == FORCE_FIELD_KIND_WIND) {
+        const bool inside = all(position >= field.position) && all(position <= field.extent);
+        return inside ? field.force : 0.0;
+    }
+
+    const float2 to_center = field.position - position;
+    const float distance = length(to_center);
+    if(distance >= field.radius || distance == 0.0) {
+        return 0.0;
+    }
+
+    // Fall off linearly to zero at the radius
+    const float magnitude = field.strength * (1.0 - distance / field.radius);
+    const float2 direction = to_center / distance;
+    if(field.kind == FORCE_FIELD_KIND_VORTEX) {
+        return float2(direction.y, -direction.x) * magnitude;
+    }
+    return direction * magnitude;
+}
+
+// One thread per cell, dispatched in 2D so large grids stay within the group count limits
+[numthreads(8, 8, 1)]
 void cs_main(uint3 tid : SV_DispatchThreadID) {
-    const uint max_grid_index = g_constant_data.grid_size.x * g_constant_data.grid_size.y;
-    if(tid.x >= max_grid_index) {
+    if(any(tid.xy >= g_constant_data.grid_size)) {
+        return;
+    }
+    const uint index = tid.x + tid.y * g_constant_data.grid_size.x;
+
+    const ObstacleCellData obstacle = g_obstacles[index];
+    if(obstacle.solid != 0) {
+        // Solid cells take the velocity of the obstacle covering them and hold no dye
+        g_velocity_field[index] = obstacle.velocity;
+        g_density_field[index] = 0.0;
         return;
     }
 
-    g_velocity_field[tid.x] = g_push_data.forced_velocity;
-    g_density_field[tid.x] = g_push_data.forced_density;
+    const float2 position = (tid.xy + 0.5) / float2(g_constant_data.grid_size);
+
+    float2 force = g_push_data.forced_velocity + g_push_data.gravity;
+    for(uint i = 0; i < g_push_data.force_field_count; ++i) {
+        force += evaluate_force_field(g_force_fields[i], position);
+    }
+
+    // Decay the existing fields and add the forces on top. With a dissipation rate of 1.0 the
+    // fields settle at the forced values.
+    const float velocity_decay = exp(-g_push_data.velocity_dissipation * g_push_data.time_step);
+    const float density_decay = exp(-g_push_data.density_dissipation * g_push_data.time_step);
+
+    g_velocity_field[index] = g_velocity_field[index] * velocity_decay + force * g_push_data.time_step;
+    g_density_field[index] = saturate(g_density_field[index] * density_decay + g_push_data.forced_density * g_push_data.time_step);
 }
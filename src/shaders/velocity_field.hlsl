@@ -31,7 +31,7 @@ VSOutput vs_main(VSInput input) {
     const float2x2 rotate_matrix = float2x2(velocity_direction.y, velocity_direction.x, -velocity_direction.x, velocity_direction.y);
     const float2 vertex_rotated_position = mul(rotate_matrix, input.position * (velocity_magnitude / 1.5));
 
-    const uint2 grid_position = uint2(input.instance_id % g_constant_data.grid_size.x, input.instance_id / g_constant_data.grid_size.y);
+    const uint2 grid_position = uint2(input.instance_id % g_constant_data.grid_size.x, input.instance_id / g_constant_data.grid_size.x);
 
     const float2 grid_position_float = grid_position / float2(g_constant_data.grid_size);
     const float2 vertex_rotated_position_in_grid_space = (vertex_rotated_position + 1.0) / 2.0;
@@ -0,0 +1,588 @@
+use std::io::{self, Read, Write};
+
+use glam::{vec2, Vec2};
+
+use crate::{
+    fluid_simulator::{ForceField, MAX_GRID_SIZE},
+    obstacles::{self, Motion, Obstacle, Shape},
+};
+
+// Binary snapshot of the full simulation state. All values are little endian.
+//
+// magic "FLUIDSIM", version u32, grid size x u32, grid size y u32, step count u64
+// parameter count u32, then per parameter: name string, value f32
+// force field count u32, then per force field: kind u32 and its values
+// obstacle count u32, then per obstacle: shape, motion, origin, initial rotation and rigid body
+// state. Mask shapes store one byte per cell.
+// field count u32, then per field: name string, component count u32
+// field data in the order of the field descriptions, grid size x * grid size y * components f32 each
+//
+// Strings are stored as a u32 byte length followed by UTF-8 bytes. Parameters and fields are looked up
+// by name on load, so new ones can be added without breaking older files. Version 1 files have no
+// step count and load with a step count of zero. Version 2 and older files have no initial rotation
+// and use the current rotation.
+const MAGIC: &[u8; 8] = b"FLUIDSIM";
+const VERSION: u32 = 3;
+// Limits on the sizes read from a file, so a corrupt header fails instead of allocating
+const MAX_STRING_LENGTH: usize = 1024;
+const MAX_FIELD_COMPONENTS: usize = 4;
+
+pub struct Snapshot {
+    pub grid_size_x: usize,
+    pub grid_size_y: usize,
+    // Steps since the initial conditions were set
+    pub step_count: u64,
+    pub parameters: Vec<(String, f32)>,
+    pub force_fields: Vec<ForceField>,
+    pub obstacles: Vec<Obstacle>,
+    // Name, component count and row major data
+    pub fields: Vec<(String, usize, Vec<f32>)>,
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn write_u32(writer: &mut impl Write, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_u64(writer: &mut impl Write, value: u64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_f32(writer: &mut impl Write, value: f32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_vec2(writer: &mut impl Write, value: Vec2) -> io::Result<()> {
+    write_f32(writer, value.x)?;
+    write_f32(writer, value.y)
+}
+
+fn write_string(writer: &mut impl Write, value: &str) -> io::Result<()> {
+    write_u32(writer, value.len() as u32)?;
+    writer.write_all(value.as_bytes())
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f32(reader: &mut impl Read) -> io::Result<f32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+fn read_vec2(reader: &mut impl Read) -> io::Result<Vec2> {
+    Ok(vec2(read_f32(reader)?, read_f32(reader)?))
+}
+
+fn read_string(reader: &mut impl Read) -> io::Result<String> {
+    let length = read_u32(reader)? as usize;
+    if length > MAX_STRING_LENGTH {
+        return Err(invalid_data(format!(
+            "string of {} bytes is too long",
+            length
+        )));
+    }
+    let mut bytes = vec![0; length];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|error| invalid_data(error.to_string()))
+}
+
+fn write_force_field(writer: &mut impl Write, field: &ForceField) -> io::Result<()> {
+    match *field {
+        ForceField::Radial {
+            center,
+            radius,
+            strength,
+        } => {
+            write_u32(writer, 0)?;
+            write_vec2(writer, center)?;
+            write_f32(writer, radius)?;
+            write_f32(writer, strength)
+        }
+        ForceField::Vortex {
+            center,
+            radius,
+            strength,
+        } => {
+            write_u32(writer, 1)?;
+            write_vec2(writer, center)?;
+            write_f32(writer, radius)?;
+            write_f32(writer, strength)
+        }
+        ForceField::Wind { min, max, force } => {
+            write_u32(writer, 2)?;
+            write_vec2(writer, min)?;
+            write_vec2(writer, max)?;
+            write_vec2(writer, force)
+        }
+    }
+}
+
+fn read_force_field(reader: &mut impl Read) -> io::Result<ForceField> {
+    Ok(match read_u32(reader)? {
+        0 => ForceField::Radial {
+            center: read_vec2(reader)?,
+            radius: read_f32(reader)?,
+            strength: read_f32(reader)?,
+        },
+        1 => ForceField::Vortex {
+            center: read_vec2(reader)?,
+            radius: read_f32(reader)?,
+            strength: read_f32(reader)?,
+        },
+        2 => ForceField::Wind {
+            min: read_vec2(reader)?,
+            max: read_vec2(reader)?,
+            force: read_vec2(reader)?,
+        },
+        kind => return Err(invalid_data(format!("unknown force field kind {}", kind))),
+    })
+}
+
+fn write_obstacle(writer: &mut impl Write, obstacle: &Obstacle) -> io::Result<()> {
+    match &obstacle.shape {
+        Shape::Circle { radius } => {
+            write_u32(writer, 0)?;
+            write_f32(writer, *radius)?;
+        }
+        Shape::Box { half_extents } => {
+            write_u32(writer, 1)?;
+            write_vec2(writer, *half_extents)?;
+        }
+        Shape::Polygon { vertices } => {
+            write_u32(writer, 2)?;
+            write_u32(writer, vertices.len() as u32)?;
+            for vertex in vertices {
+                write_vec2(writer, *vertex)?;
+            }
+        }
+        Shape::Mask {
+            half_extents,
+            width,
+            height,
+            solid,
+        } => {
+            write_u32(writer, 3)?;
+            write_vec2(writer, *half_extents)?;
+            write_u32(writer, *width as u32)?;
+            write_u32(writer, *height as u32)?;
+            let bytes = solid.iter().map(|&solid| solid as u8).collect::<Vec<_>>();
+            writer.write_all(&bytes)?;
+        }
+    }
+
+    match obstacle.motion {
+        Motion::Static => write_u32(writer, 0)?,
+        Motion::Oscillate {
+            amplitude,
+            frequency,
+        } => {
+            write_u32(writer, 1)?;
+            write_vec2(writer, amplitude)?;
+            write_f32(writer, frequency)?;
+        }
+        Motion::Orbit { radius, frequency } => {
+            write_u32(writer, 2)?;
+            write_f32(writer, radius)?;
+            write_f32(writer, frequency)?;
+        }
+        Motion::Rotate { angular_velocity } => {
+            write_u32(writer, 3)?;
+            write_f32(writer, angular_velocity)?;
+        }
+        Motion::Dynamic {
+            mass,
+            inertia,
+            drag,
+        } => {
+            write_u32(writer, 4)?;
+            write_f32(writer, mass)?;
+            write_f32(writer, inertia)?;
+            write_f32(writer, drag)?;
+        }
+    }
+
+    write_vec2(writer, obstacle.origin)?;
+    write_f32(writer, obstacle.initial_rotation)?;
+    write_vec2(writer, obstacle.position)?;
+    write_f32(writer, obstacle.rotation)?;
+    write_vec2(writer, obstacle.velocity)?;
+    write_f32(writer, obstacle.angular_velocity)
+}
+
+fn read_obstacle(reader: &mut impl Read, version: u32) -> io::Result<Obstacle> {
+    let shape = match read_u32(reader)? {
+        0 => Shape::Circle {
+            radius: read_f32(reader)?,
+        },
+        1 => Shape::Box {
+            half_extents: read_vec2(reader)?,
+        },
+        2 => {
+            let count = read_u32(reader)?;
+            let vertices = (0..count)
+                .map(|_| read_vec2(reader))
+                .collect::<io::Result<_>>()?;
+            Shape::Polygon { vertices }
+        }
+        3 => {
+            let half_extents = read_vec2(reader)?;
+            let width = read_u32(reader)? as usize;
+            let height = read_u32(reader)? as usize;
+            obstacles::check_mask_size(width, height).map_err(invalid_data)?;
+            let mut bytes = vec![0; width * height];
+            reader.read_exact(&mut bytes)?;
+            Shape::Mask {
+                half_extents,
+                width,
+                height,
+                solid: bytes.into_iter().map(|byte| byte != 0).collect(),
+            }
+        }
+        kind => return Err(invalid_data(format!("unknown obstacle shape {}", kind))),
+    };
+
+    let motion = match read_u32(reader)? {
+        0 => Motion::Static,
+        1 => Motion::Oscillate {
+            amplitude: read_vec2(reader)?,
+            frequency: read_f32(reader)?,
+        },
+        2 => Motion::Orbit {
+            radius: read_f32(reader)?,
+            frequency: read_f32(reader)?,
+        },
+        3 => Motion::Rotate {
+            angular_velocity: read_f32(reader)?,
+        },
+        4 => Motion::Dynamic {
+            mass: read_f32(reader)?,
+            inertia: read_f32(reader)?,
+            drag: read_f32(reader)?,
+        },
+        kind => return Err(invalid_data(format!("unknown obstacle motion {}", kind))),
+    };
+    motion.validate().map_err(invalid_data)?;
+
+    let mut obstacle = Obstacle::new(shape, Vec2::ZERO, motion);
+    obstacle.origin = read_vec2(reader)?;
+    let initial_rotation = if version >= 3 {
+        Some(read_f32(reader)?)
+    } else {
+        None
+    };
+    obstacle.position = read_vec2(reader)?;
+    obstacle.rotation = read_f32(reader)?;
+    obstacle.initial_rotation = initial_rotation.unwrap_or(obstacle.rotation);
+    obstacle.velocity = read_vec2(reader)?;
+    obstacle.angular_velocity = read_f32(reader)?;
+    Ok(obstacle)
+}
+
+impl Snapshot {
+    pub fn field(&self, name: &str, components: usize) -> Option<&[f32]> {
+        self.fields
+            .iter()
+            .find(|(field, field_components, _)| field == name && *field_components == components)
+            .map(|(_, _, data)| data.as_slice())
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        write_u32(writer, VERSION)?;
+        write_u32(writer, self.grid_size_x as u32)?;
+        write_u32(writer, self.grid_size_y as u32)?;
+        write_u64(writer, self.step_count)?;
+
+        write_u32(writer, self.parameters.len() as u32)?;
+        for (name, value) in &self.parameters {
+            write_string(writer, name)?;
+            write_f32(writer, *value)?;
+        }
+
+        write_u32(writer, self.force_fields.len() as u32)?;
+        for field in &self.force_fields {
+            write_force_field(writer, field)?;
+        }
+
+        write_u32(writer, self.obstacles.len() as u32)?;
+        for obstacle in &self.obstacles {
+            write_obstacle(writer, obstacle)?;
+        }
+
+        write_u32(writer, self.fields.len() as u32)?;
+        for (name, components, _) in &self.fields {
+            write_string(writer, name)?;
+            write_u32(writer, *components as u32)?;
+        }
+        for (_, _, data) in &self.fields {
+            for value in data {
+                write_f32(writer, *value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn read(reader: &mut impl Read) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a fluid simulator snapshot"));
+        }
+        let version = read_u32(reader)?;
+        if !(1..=VERSION).contains(&version) {
+            return Err(invalid_data(format!(
+                "unsupported snapshot version {}",
+                version
+            )));
+        }
+        let grid_size_x = read_u32(reader)? as usize;
+        let grid_size_y = read_u32(reader)? as usize;
+        if !(1..=MAX_GRID_SIZE).contains(&grid_size_x)
+            || !(1..=MAX_GRID_SIZE).contains(&grid_size_y)
+        {
+            return Err(invalid_data(format!(
+                "unsupported grid size {}x{}",
+                grid_size_x, grid_size_y
+            )));
+        }
+
+        let step_count = if version >= 2 { read_u64(reader)? } else { 0 };
+
+        let parameter_count = read_u32(reader)?;
+        let parameters = (0..parameter_count)
+            .map(|_| Ok((read_string(reader)?, read_f32(reader)?)))
+            .collect::<io::Result<_>>()?;
+
+        let force_field_count = read_u32(reader)?;
+        let force_fields = (0..force_field_count)
+            .map(|_| read_force_field(reader))
+            .collect::<io::Result<_>>()?;
+
+        let obstacle_count = read_u32(reader)?;
+        let obstacles = (0..obstacle_count)
+            .map(|_| read_obstacle(reader, version))
+            .collect::<io::Result<_>>()?;
+
+        let field_count = read_u32(reader)?;
+        let descriptions = (0..field_count)
+            .map(|_| Ok((read_string(reader)?, read_u32(reader)? as usize)))
+            .collect::<io::Result<Vec<_>>>()?;
+        let fields = descriptions
+            .into_iter()
+            .map(|(name, components)| {
+                let length = Some(components)
+                    .filter(|components| (1..=MAX_FIELD_COMPONENTS).contains(components))
+                    .and_then(|components| {
+                        grid_size_x
+                            .checked_mul(grid_size_y)?
+                            .checked_mul(components)
+                    })
+                    .ok_or_else(|| {
+                        invalid_data(format!("field {} has {} components", name, components))
+                    })?;
+                let data = (0..length)
+                    .map(|_| read_f32(reader))
+                    .collect::<io::Result<_>>()?;
+                Ok((name, components, data))
+            })
+            .collect::<io::Result<_>>()?;
+
+        Ok(Self {
+            grid_size_x,
+            grid_size_y,
+            step_count,
+            parameters,
+            force_fields,
+            obstacles,
+            fields,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        let mut obstacle = Obstacle::new(
+            Shape::Mask {
+                half_extents: vec2(0.1, 0.2),
+                width: 2,
+                height: 3,
+                solid: vec![true, false, false, true, true, false],
+            },
+            vec2(0.25, 0.5),
+            Motion::Dynamic {
+                mass: 2.0,
+                inertia: 0.5,
+                drag: 0.1,
+            },
+        );
+        obstacle.initial_rotation = 0.1;
+        obstacle.rotation = 0.3;
+        obstacle.velocity = vec2(1.0, -1.0);
+
+        Snapshot {
+            grid_size_x: 3,
+            grid_size_y: 2,
+            step_count: 1 << 40,
+            parameters: vec![
+                ("time_step".to_string(), 0.01),
+                ("gravity.y".to_string(), -9.8),
+            ],
+            force_fields: vec![
+                ForceField::Vortex {
+                    center: vec2(0.5, 0.5),
+                    radius: 0.25,
+                    strength: 2.0,
+                },
+                ForceField::Wind {
+                    min: vec2(0.0, 0.0),
+                    max: vec2(1.0, 0.5),
+                    force: vec2(0.5, 0.0),
+                },
+            ],
+            obstacles: vec![
+                obstacle,
+                Obstacle::new(
+                    Shape::Polygon {
+                        vertices: vec![vec2(0.0, 0.0), vec2(0.1, 0.0), vec2(0.0, 0.1)],
+                    },
+                    vec2(0.75, 0.5),
+                    Motion::Rotate {
+                        angular_velocity: 1.0,
+                    },
+                ),
+            ],
+            fields: vec![
+                (
+                    "velocity".to_string(),
+                    2,
+                    (0..12).map(|i| i as f32).collect(),
+                ),
+                ("density".to_string(), 1, vec![0.0, 0.5, 1.0, 1.5, 2.0, 2.5]),
+            ],
+        }
+    }
+
+    fn bytes(snapshot: &Snapshot) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        snapshot.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let snapshot = snapshot();
+        let read = Snapshot::read(&mut bytes(&snapshot).as_slice()).unwrap();
+
+        assert_eq!(read.grid_size_x, snapshot.grid_size_x);
+        assert_eq!(read.grid_size_y, snapshot.grid_size_y);
+        assert_eq!(read.step_count, snapshot.step_count);
+        assert_eq!(read.parameters, snapshot.parameters);
+        assert_eq!(read.force_fields, snapshot.force_fields);
+        assert_eq!(read.obstacles, snapshot.obstacles);
+        assert_eq!(read.fields, snapshot.fields);
+    }
+
+    #[test]
+    fn reads_older_versions() {
+        let mut snapshot = snapshot();
+        snapshot.obstacles.truncate(1);
+        let obstacle = &snapshot.obstacles[0];
+        let mut bytes = bytes(&snapshot);
+
+        // Version 2 has no initial rotation after the origin
+        bytes[8..12].copy_from_slice(&2u32.to_le_bytes());
+        let origin: Vec<u8> = [obstacle.origin.x, obstacle.origin.y]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        let origin_end = bytes
+            .windows(origin.len())
+            .position(|window| window == origin)
+            .unwrap()
+            + origin.len();
+        bytes.drain(origin_end..origin_end + 4);
+        let read = Snapshot::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read.step_count, snapshot.step_count);
+        assert_eq!(read.obstacles[0].initial_rotation, obstacle.rotation);
+        assert_eq!(read.obstacles[0].origin, obstacle.origin);
+
+        // Version 1 has no step count after the grid size either
+        bytes[8..12].copy_from_slice(&1u32.to_le_bytes());
+        bytes.drain(20..28);
+        let read = Snapshot::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read.step_count, 0);
+        assert_eq!(read.parameters, snapshot.parameters);
+        assert_eq!(read.fields, snapshot.fields);
+    }
+
+    #[test]
+    fn rejects_truncated_file() {
+        let bytes = bytes(&snapshot());
+        let error = Snapshot::read(&mut &bytes[..bytes.len() - 1])
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn rejects_oversized_counts() {
+        let mut bytes = bytes(&snapshot());
+        // Grid size x
+        bytes[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        let error = Snapshot::read(&mut bytes.as_slice()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // Length of the first parameter name
+        let mut bytes = self::bytes(&snapshot());
+        bytes[32..36].copy_from_slice(&u32::MAX.to_le_bytes());
+        let error = Snapshot::read(&mut bytes.as_slice()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_non_finite_mass() {
+        let mut snapshot = snapshot();
+        snapshot.obstacles[0].motion = Motion::Dynamic {
+            mass: f32::NAN,
+            inertia: 0.001,
+            drag: 1.0,
+        };
+        let error = Snapshot::read(&mut bytes(&snapshot).as_slice())
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_empty_mask() {
+        let mut snapshot = snapshot();
+        snapshot.obstacles[0].shape = Shape::Mask {
+            half_extents: vec2(0.1, 0.1),
+            width: 0,
+            height: 3,
+            solid: Vec::new(),
+        };
+        let error = Snapshot::read(&mut bytes(&snapshot).as_slice())
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,288 @@
+use wgpu::{PushConstantRange, ShaderStages};
+
+use crate::shaders::spirv;
+
+// Jacobi iterations of the pressure equation per step, on the GPU and in the CPU solver. Even, so
+// the last iteration writes the pressure buffer. The pressure of the previous step is the starting
+// guess.
+pub(crate) const PRESSURE_ITERATIONS: usize = 40;
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct PushConstants {
+    time_step: f32,
+}
+
+unsafe impl bytemuck::Pod for PushConstants {}
+unsafe impl bytemuck::Zeroable for PushConstants {}
+
+fn uniform_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            has_dynamic_offset: false,
+            min_binding_size: None,
+            ty: wgpu::BufferBindingType::Uniform,
+        },
+        count: None,
+    }
+}
+
+fn storage_entry(binding: u32, read_only: bool) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            has_dynamic_offset: false,
+            min_binding_size: None,
+            ty: wgpu::BufferBindingType::Storage { read_only },
+        },
+        count: None,
+    }
+}
+
+fn buffer_entry(binding: u32, buffer: &wgpu::Buffer) -> wgpu::BindGroupEntry<'_> {
+    wgpu::BindGroupEntry {
+        binding,
+        resource: buffer.as_entire_binding(),
+    }
+}
+
+fn field_buffer(device: &wgpu::Device, label: &str, size: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        usage: wgpu::BufferUsages::STORAGE
+            | wgpu::BufferUsages::COPY_SRC
+            | wgpu::BufferUsages::COPY_DST,
+        size,
+        mapped_at_creation: false,
+    })
+}
+
+// Advection and pressure projection passes of the simulation step, run after the force pass. See
+// solver.hlsl for the discretization, which the CPU solver follows.
+pub(crate) struct Solver {
+    advect_pipeline: wgpu::ComputePipeline,
+    divergence_pipeline: wgpu::ComputePipeline,
+    jacobi_pipeline: wgpu::ComputePipeline,
+    subtract_gradient_pipeline: wgpu::ComputePipeline,
+    // The first reads the pressure and writes the next pressure, the second the other way around
+    bind_groups: [wgpu::BindGroup; 2],
+    advected_velocity_buffer: wgpu::Buffer,
+    advected_density_buffer: wgpu::Buffer,
+    _divergence_buffer: wgpu::Buffer,
+    pressure_buffer: wgpu::Buffer,
+    _next_pressure_buffer: wgpu::Buffer,
+    grid_size_x: usize,
+    grid_size_y: usize,
+    // Size of a scalar field in bytes
+    scalar_size: u64,
+}
+
+impl Solver {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        constants_buffer: &wgpu::Buffer,
+        velocity_buffer: &wgpu::Buffer,
+        density_buffer: &wgpu::Buffer,
+        obstacles_buffer: &wgpu::Buffer,
+        grid_size_x: usize,
+        grid_size_y: usize,
+    ) -> Self {
+        let scalar_size = (grid_size_x * grid_size_y * std::mem::size_of::<f32>()) as u64;
+        let advected_velocity_buffer =
+            field_buffer(device, "advected_velocity_buffer", 2 * scalar_size);
+        let advected_density_buffer = field_buffer(device, "advected_density_buffer", scalar_size);
+        let divergence_buffer = field_buffer(device, "divergence_buffer", scalar_size);
+        let pressure_buffer = field_buffer(device, "pressure_buffer", scalar_size);
+        let next_pressure_buffer = field_buffer(device, "next_pressure_buffer", scalar_size);
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("solver_bind_group_layout"),
+            entries: &[
+                uniform_entry(0),
+                storage_entry(1, false),
+                storage_entry(2, false),
+                storage_entry(3, true),
+                storage_entry(4, false),
+                storage_entry(5, false),
+                storage_entry(6, false),
+                storage_entry(7, true),
+                storage_entry(8, false),
+            ],
+        });
+        let bind_group = |pressure: &wgpu::Buffer, next_pressure: &wgpu::Buffer| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("solver_bind_group"),
+                layout: &bind_group_layout,
+                entries: &[
+                    buffer_entry(0, constants_buffer),
+                    buffer_entry(1, velocity_buffer),
+                    buffer_entry(2, density_buffer),
+                    buffer_entry(3, obstacles_buffer),
+                    buffer_entry(4, &advected_velocity_buffer),
+                    buffer_entry(5, &advected_density_buffer),
+                    buffer_entry(6, &divergence_buffer),
+                    buffer_entry(7, pressure),
+                    buffer_entry(8, next_pressure),
+                ],
+            })
+        };
+        let bind_groups = [
+            bind_group(&pressure_buffer, &next_pressure_buffer),
+            bind_group(&next_pressure_buffer, &pressure_buffer),
+        ];
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("solver_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[PushConstantRange {
+                stages: ShaderStages::COMPUTE,
+                range: 0..std::mem::size_of::<PushConstants>() as u32,
+            }],
+        });
+        let pipeline = |label: &str, code: Vec<u8>, entry_point: &str| {
+            let module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+                label: Some(label),
+                source: wgpu::util::make_spirv(code.as_slice()),
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point,
+            })
+        };
+        let advect_pipeline = pipeline(
+            "solver_advect_pipeline",
+            spirv!("solver", "cs_advect", "cs_6_6"),
+            "cs_advect",
+        );
+        let divergence_pipeline = pipeline(
+            "solver_divergence_pipeline",
+            spirv!("solver", "cs_divergence", "cs_6_6"),
+            "cs_divergence",
+        );
+        let jacobi_pipeline = pipeline(
+            "solver_jacobi_pipeline",
+            spirv!("solver", "cs_jacobi", "cs_6_6"),
+            "cs_jacobi",
+        );
+        let subtract_gradient_pipeline = pipeline(
+            "solver_subtract_gradient_pipeline",
+            spirv!("solver", "cs_subtract_gradient", "cs_6_6"),
+            "cs_subtract_gradient",
+        );
+
+        Self {
+            advect_pipeline,
+            divergence_pipeline,
+            jacobi_pipeline,
+            subtract_gradient_pipeline,
+            bind_groups,
+            advected_velocity_buffer,
+            advected_density_buffer,
+            _divergence_buffer: divergence_buffer,
+            pressure_buffer,
+            _next_pressure_buffer: next_pressure_buffer,
+            grid_size_x,
+            grid_size_y,
+            scalar_size,
+        }
+    }
+
+    // Pressure of the last projection, zero in solid cells
+    pub(crate) fn pressure_buffer(&self) -> &wgpu::Buffer {
+        &self.pressure_buffer
+    }
+
+    // Starts the next projection from zero pressure, for when the fields are replaced
+    pub(crate) fn clear_pressure(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.pressure_buffer,
+            0,
+            &vec![0; self.scalar_size as usize],
+        );
+    }
+
+    // Runs the pipeline once over every cell
+    fn dispatch<'a>(
+        &'a self,
+        pass: &mut wgpu::ComputePass<'a>,
+        pipeline: &'a wgpu::ComputePipeline,
+        bind_group: &'a wgpu::BindGroup,
+        time_step: f32,
+    ) {
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.set_push_constants(0, bytemuck::cast_slice(&[PushConstants { time_step }]));
+        pass.dispatch(
+            (self.grid_size_x as u32).div_ceil(8),
+            (self.grid_size_y as u32).div_ceil(8),
+            1,
+        );
+    }
+
+    // Moves the velocity and density along the velocity
+    pub(crate) fn record_advection(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        velocity_buffer: &wgpu::Buffer,
+        density_buffer: &wgpu::Buffer,
+        time_step: f32,
+    ) {
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("advection_compute_pass"),
+            });
+            self.dispatch(
+                &mut pass,
+                &self.advect_pipeline,
+                &self.bind_groups[0],
+                time_step,
+            );
+        }
+        encoder.copy_buffer_to_buffer(
+            &self.advected_velocity_buffer,
+            0,
+            velocity_buffer,
+            0,
+            2 * self.scalar_size,
+        );
+        encoder.copy_buffer_to_buffer(
+            &self.advected_density_buffer,
+            0,
+            density_buffer,
+            0,
+            self.scalar_size,
+        );
+    }
+
+    // Makes the velocity divergence free
+    pub(crate) fn record_projection(&self, encoder: &mut wgpu::CommandEncoder, time_step: f32) {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("projection_compute_pass"),
+        });
+        self.dispatch(
+            &mut pass,
+            &self.divergence_pipeline,
+            &self.bind_groups[0],
+            time_step,
+        );
+        for iteration in 0..PRESSURE_ITERATIONS {
+            self.dispatch(
+                &mut pass,
+                &self.jacobi_pipeline,
+                &self.bind_groups[iteration % 2],
+                time_step,
+            );
+        }
+        self.dispatch(
+            &mut pass,
+            &self.subtract_gradient_pipeline,
+            &self.bind_groups[0],
+            time_step,
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use std::f32::consts::TAU;
+
+use glam::{vec2, Vec2};
+
+use crate::{cpu_solver::CpuSolver, scene::Parameters};
+
+// Flows with known solutions and the error norms the solver is measured with. The fluid_simulator
+// example runs them on the GPU with --verify, the tests below on the CPU solver, which follows the
+// same discretization.
+
+// Kinematic viscosity of the Taylor-Green vortex
+pub const VISCOSITY: f32 = 0.01;
+pub const TAYLOR_GREEN_WAVE_NUMBER: f32 = TAU;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flow {
+    // u = sin(kx) cos(ky) F(t), v = -cos(kx) sin(ky) F(t) with F(t) = exp(-2 nu k^2 t). Advection
+    // is balanced by the pressure gradient for this flow and the velocity normal to the walls is
+    // zero, so the viscous decay is the whole solution and is modelled by the velocity dissipation.
+    TaylorGreen,
+    // Uniform dye in fluid at rest, driven by the forced density against the dissipation,
+    // dc/dt = f - d c. Has no spatial error.
+    DyeRelaxation,
+}
+
+// Error norms over all the cells
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ErrorNorms {
+    pub l1: f32,
+    pub l2: f32,
+    pub linf: f32,
+}
+
+impl ErrorNorms {
+    pub fn new(errors: &[f32]) -> Self {
+        let count = errors.len() as f32;
+        Self {
+            l1: errors.iter().sum::<f32>() / count,
+            l2: (errors.iter().map(|error| error * error).sum::<f32>() / count).sqrt(),
+            linf: errors.iter().copied().fold(0.0, f32::max),
+        }
+    }
+
+    // Order of accuracy observed from the L2 error of the level before, which had twice the cell
+    // size or time step
+    pub fn observed_order(&self, previous: &ErrorNorms) -> f32 {
+        (previous.l2 / self.l2).log2()
+    }
+}
+
+fn cell_centers(grid_size_x: usize, grid_size_y: usize) -> impl Iterator<Item = Vec2> {
+    (0..grid_size_x * grid_size_y).map(move |index| {
+        (vec2((index % grid_size_x) as f32, (index / grid_size_x) as f32) + 0.5)
+            / vec2(grid_size_x as f32, grid_size_y as f32)
+    })
+}
+
+impl Flow {
+    pub const ALL: [Flow; 2] = [Flow::TaylorGreen, Flow::DyeRelaxation];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Flow::TaylorGreen => "Taylor-Green vortex",
+            Flow::DyeRelaxation => "Dye relaxation",
+        }
+    }
+
+    // Uniform flows have no spatial error, so only the time step is refined for them. The error of
+    // the semi-Lagrangian advection grows as the time step shrinks on a fixed grid, so only the
+    // grid is refined for the others, with the time step proportional to the cell size.
+    pub fn refines_grid(&self) -> bool {
+        *self == Flow::TaylorGreen
+    }
+
+    pub fn parameters(&self, time_step: f32) -> Parameters {
+        let (forced_density, velocity_dissipation) = match self {
+            Flow::TaylorGreen => (0.0, 2.0 * VISCOSITY * TAYLOR_GREEN_WAVE_NUMBER.powi(2)),
+            Flow::DyeRelaxation => (0.5, 1.0),
+        };
+        Parameters {
+            time_step,
+            forced_velocity: Vec2::ZERO,
+            forced_density,
+            velocity_dissipation,
+            density_dissipation: 1.0,
+            gravity: Vec2::ZERO,
+        }
+    }
+
+    fn exact_velocity(&self, position: Vec2, time: f32) -> Vec2 {
+        match self {
+            Flow::TaylorGreen => {
+                let dissipation = self.parameters(0.0).velocity_dissipation;
+                let (sin_x, cos_x) = (TAYLOR_GREEN_WAVE_NUMBER * position.x).sin_cos();
+                let (sin_y, cos_y) = (TAYLOR_GREEN_WAVE_NUMBER * position.y).sin_cos();
+                vec2(sin_x * cos_y, -cos_x * sin_y) * (-dissipation * time).exp()
+            }
+            Flow::DyeRelaxation => Vec2::ZERO,
+        }
+    }
+
+    fn exact_density(&self, time: f32) -> f32 {
+        let parameters = self.parameters(0.0);
+        let dissipation = parameters.density_dissipation;
+        parameters.forced_density / dissipation * (1.0 - (-dissipation * time).exp())
+    }
+
+    // Velocity and density at time zero
+    pub fn initial_fields(&self, grid_size_x: usize, grid_size_y: usize) -> (Vec<Vec2>, Vec<f32>) {
+        (
+            cell_centers(grid_size_x, grid_size_y)
+                .map(|position| self.exact_velocity(position, 0.0))
+                .collect(),
+            vec![self.exact_density(0.0); grid_size_x * grid_size_y],
+        )
+    }
+
+    // Per cell error of the field the flow is about
+    pub fn errors(
+        &self,
+        grid_size_x: usize,
+        grid_size_y: usize,
+        time: f32,
+        velocity: &[Vec2],
+        density: &[f32],
+    ) -> Vec<f32> {
+        match self {
+            Flow::TaylorGreen => velocity
+                .iter()
+                .zip(cell_centers(grid_size_x, grid_size_y))
+                .map(|(velocity, position)| velocity.distance(self.exact_velocity(position, time)))
+                .collect(),
+            Flow::DyeRelaxation => {
+                let exact = self.exact_density(time);
+                density
+                    .iter()
+                    .map(|density| (density - exact).abs())
+                    .collect()
+            }
+        }
+    }
+
+    // Runs the flow on the CPU solver up to the end time and measures the error there
+    pub fn run_on_cpu(&self, grid_size: usize, time_step: f32, end_time: f32) -> ErrorNorms {
+        let (velocity, density) = self.initial_fields(grid_size, grid_size);
+        let mut solver = CpuSolver::new(grid_size, grid_size, velocity, density);
+        let parameters = self.parameters(time_step);
+
+        let steps = (end_time / time_step).round() as u32;
+        for _ in 0..steps {
+            solver.step_with(&parameters, &[], &[]);
+        }
+        ErrorNorms::new(&self.errors(
+            grid_size,
+            grid_size,
+            steps as f32 * time_step,
+            &solver.velocity,
+            &solver.density,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn taylor_green_converges_with_the_grid() {
+        // Courant number of 0.25 for the peak velocity of one. Semi-Lagrangian advection is first
+        // order at a fixed Courant number.
+        let levels: Vec<_> = [16, 32, 64]
+            .iter()
+            .map(|&grid_size| {
+                Flow::TaylorGreen.run_on_cpu(grid_size, 0.25 / grid_size as f32, 0.25)
+            })
+            .collect();
+        for pair in levels.windows(2) {
+            let order = pair[1].observed_order(&pair[0]);
+            assert!(order > 0.8, "observed order {}", order);
+        }
+    }
+}
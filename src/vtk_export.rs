@@ -0,0 +1,166 @@
+use std::io::{self, Write};
+
+use glam::Vec2;
+
+// VTK reads ASCII values with the C++ stream operators, which do not accept NaN or infinities.
+// These are written as zero and the largest finite value of the same sign instead.
+fn finite(value: f32) -> f32 {
+    if value.is_nan() {
+        0.0
+    } else {
+        value.clamp(f32::MIN, f32::MAX)
+    }
+}
+
+// Writes the fields as VTK XML image data (.vti) with one cell per grid cell, in the unit square.
+// Velocity is written as a 3 component vector so ParaView can use it for glyphs and stream tracers.
+// The finite array is 0 in the cells where a non-finite value was replaced, see finite.
+pub fn write_image_data(
+    writer: &mut impl Write,
+    grid_size_x: usize,
+    grid_size_y: usize,
+    velocity: &[Vec2],
+    density: &[f32],
+) -> io::Result<()> {
+    writeln!(writer, r#"<?xml version="1.0"?>"#)?;
+    writeln!(
+        writer,
+        r#"<VTKFile type="ImageData" version="0.1" byte_order="LittleEndian">"#
+    )?;
+    writeln!(
+        writer,
+        r#"  <ImageData WholeExtent="0 {} 0 {} 0 0" Origin="0 0 0" Spacing="{} {} 1">"#,
+        grid_size_x,
+        grid_size_y,
+        1.0 / grid_size_x as f32,
+        1.0 / grid_size_y as f32
+    )?;
+    writeln!(
+        writer,
+        r#"    <Piece Extent="0 {} 0 {} 0 0">"#,
+        grid_size_x, grid_size_y
+    )?;
+    writeln!(
+        writer,
+        r#"      <CellData Scalars="density" Vectors="velocity">"#
+    )?;
+
+    writeln!(
+        writer,
+        r#"        <DataArray type="Float32" Name="density" format="ascii">"#
+    )?;
+    for row in density.chunks(grid_size_x) {
+        write!(writer, "         ")?;
+        for value in row {
+            write!(writer, " {}", finite(*value))?;
+        }
+        writeln!(writer)?;
+    }
+    writeln!(writer, "        </DataArray>")?;
+
+    writeln!(
+        writer,
+        r#"        <DataArray type="Float32" Name="velocity" NumberOfComponents="3" format="ascii">"#
+    )?;
+    for row in velocity.chunks(grid_size_x) {
+        write!(writer, "         ")?;
+        for value in row {
+            write!(writer, " {} {} 0", finite(value.x), finite(value.y))?;
+        }
+        writeln!(writer)?;
+    }
+    writeln!(writer, "        </DataArray>")?;
+
+    writeln!(
+        writer,
+        r#"        <DataArray type="UInt8" Name="finite" format="ascii">"#
+    )?;
+    for (velocity_row, density_row) in velocity
+        .chunks(grid_size_x)
+        .zip(density.chunks(grid_size_x))
+    {
+        write!(writer, "         ")?;
+        for (velocity, density) in velocity_row.iter().zip(density_row) {
+            let is_finite = velocity.is_finite() && density.is_finite();
+            write!(writer, " {}", is_finite as u8)?;
+        }
+        writeln!(writer)?;
+    }
+    writeln!(writer, "        </DataArray>")?;
+
+    writeln!(writer, "      </CellData>")?;
+    writeln!(writer, "    </Piece>")?;
+    writeln!(writer, "  </ImageData>")?;
+    writeln!(writer, "</VTKFile>")
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec2;
+
+    use super::*;
+
+    fn write(velocity: &[Vec2], density: &[f32]) -> String {
+        let mut bytes = Vec::new();
+        write_image_data(&mut bytes, 2, 2, velocity, density).unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    // Values of the named data array, parsed the way VTK does, which fails on NaN and inf
+    fn array(text: &str, name: &str) -> Vec<f32> {
+        let start = text.find(&format!(r#"Name="{}""#, name)).unwrap();
+        let start = start + text[start..].find('>').unwrap() + 1;
+        let end = start + text[start..].find("</DataArray>").unwrap();
+        text[start..end]
+            .split_whitespace()
+            .map(|value| {
+                assert!(value.bytes().all(|byte| b"0123456789.-+e".contains(&byte)));
+                value.parse().unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let velocity = [
+            vec2(0.0, 1.0),
+            vec2(-0.5, 0.25),
+            vec2(1e-7, 3.0),
+            vec2(2.0, -1.0),
+        ];
+        let density = [0.0, 0.125, 1.0, 0.75];
+        let text = write(&velocity, &density);
+
+        assert!(text.contains(
+            r#"<ImageData WholeExtent="0 2 0 2 0 0" Origin="0 0 0" Spacing="0.5 0.5 1">"#
+        ));
+        assert_eq!(array(&text, "density"), density);
+        assert_eq!(
+            array(&text, "velocity"),
+            velocity
+                .iter()
+                .flat_map(|velocity| [velocity.x, velocity.y, 0.0])
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(array(&text, "finite"), [1.0; 4]);
+    }
+
+    #[test]
+    fn replaces_non_finite_values() {
+        let velocity = [
+            vec2(f32::NAN, 1.0),
+            vec2(0.0, f32::INFINITY),
+            Vec2::ZERO,
+            Vec2::ZERO,
+        ];
+        let density = [0.5, 0.5, f32::NEG_INFINITY, 0.5];
+        let text = write(&velocity, &density);
+
+        assert_eq!(array(&text, "density"), [0.5, 0.5, f32::MIN, 0.5]);
+        assert_eq!(
+            array(&text, "velocity")[..6],
+            [0.0, 1.0, 0.0, 0.0, f32::MAX, 0.0]
+        );
+        assert_eq!(array(&text, "finite"), [0.0, 0.0, 0.0, 1.0]);
+    }
+}
@@ -0,0 +1,66 @@
+// Runs the GPU step and the CPU reference solver side by side, like --compare-cpu. Skipped when
+// the machine has no adapter that supports the features the simulator needs.
+
+use fluid_simulator::{
+    cpu_solver::CpuSolver,
+    device,
+    obstacles::{Motion, Obstacle, Shape},
+    FluidSimulator, ForceField,
+};
+use glam::vec2;
+
+const TOLERANCE: f32 = 1e-4;
+
+#[test]
+fn gpu_matches_cpu_solver() {
+    let (device, queue, _info) = match device::create_headless_device(wgpu::Backends::PRIMARY, None)
+    {
+        Ok(device) => device,
+        Err(error) => {
+            eprintln!("Skipping GPU comparison: {}", error);
+            return;
+        }
+    };
+
+    let mut simulator =
+        FluidSimulator::with_device(device, queue, wgpu::TextureFormat::Rgba8Unorm, 48, 32);
+    simulator.forced_velocity = vec2(0.2, 0.0);
+    simulator.forced_density = 0.3;
+    simulator.velocity_dissipation = 0.5;
+    simulator.density_dissipation = 0.1;
+    simulator.gravity = vec2(0.0, -0.1);
+    simulator.force_fields.push(ForceField::Vortex {
+        center: vec2(0.3, 0.5),
+        radius: 0.2,
+        strength: 2.0,
+    });
+    simulator.force_fields.push(ForceField::Wind {
+        min: vec2(0.6, 0.0),
+        max: vec2(0.8, 1.0),
+        force: vec2(0.0, 1.0),
+    });
+    simulator.obstacles.push(Obstacle::new(
+        Shape::Circle { radius: 0.05 },
+        vec2(0.5, 0.5),
+        Motion::Orbit {
+            radius: 0.1,
+            frequency: 1.0,
+        },
+    ));
+
+    let mut cpu_solver = CpuSolver::from_simulator(&simulator);
+    for _ in 0..20 {
+        simulator.run_step();
+        cpu_solver.step(&simulator);
+    }
+
+    let difference = cpu_solver.difference(
+        &simulator.read_velocity_field(),
+        &simulator.read_density_field(),
+    );
+    assert!(
+        difference.within(TOLERANCE),
+        "fields differ by {:?}",
+        difference
+    );
+}